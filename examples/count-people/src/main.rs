@@ -52,12 +52,12 @@ fn main() {
             →
         number <P1> <N2>
         ◊
-    ");
-    kb.tell( "number john 1 ◊" );
-    kb.tell( "person john ◊" );
-    kb.tell( "person susan ◊" );
-    kb.tell( "person joe ◊" );
-    kb.tell( "person tim ◊" );
-    kb.tell( "person hel ◊" );
-    kb.tell( "person sue ◊" );
+    ").unwrap();
+    kb.tell( "number john 1 ◊" ).unwrap();
+    kb.tell( "person john ◊" ).unwrap();
+    kb.tell( "person susan ◊" ).unwrap();
+    kb.tell( "person joe ◊" ).unwrap();
+    kb.tell( "person tim ◊" ).unwrap();
+    kb.tell( "person hel ◊" ).unwrap();
+    kb.tell( "person sue ◊" ).unwrap();
 }
@@ -25,7 +25,6 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
-use std::mem;
 use structopt::StructOpt;
 //use std::{thread, time};
 
@@ -48,6 +47,7 @@ fn main() {
     env_logger::init();
     let kb = kb::KBGenerator::gen_kb();
     let opt = Opt::from_args();
+    kb.on_output(|output| println!("{}", output.text));
     kb.tell("
 
         q <N> 
@@ -73,10 +73,10 @@ fn main() {
             →
         {<{ fib <N> <Val> }>} ◊
 
-    ");
+    ").unwrap();
     let query = format!("q {} ◊", opt.n);
-    kb.tell( unsafe { mem::transmute( query.as_str() ) });
+    kb.tell(&query).unwrap();
 
-    kb.tell("fib 0 0 ◊");
-    kb.tell("fib 1 1 ◊");
+    kb.tell("fib 0 0 ◊").unwrap();
+    kb.tell("fib 1 1 ◊").unwrap();
 }
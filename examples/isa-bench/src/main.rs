@@ -25,7 +25,6 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
-use std::mem;
 use std::time::SystemTime;
 use structopt::StructOpt;
 //use std::{thread, time};
@@ -75,22 +74,22 @@ fn main() {
     for r in 0..opt.rules {
         start += 1;
         let f1 = format!("<X0> ISA{start} <X1> -> <X1> IS{start} <X2> -> <X0> ISA{start} <X2> ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+        kb.tell(&f1).unwrap();
         let f2 = format!("<X0> IS{start} <X1> -> <X1> IS{start} <X2> -> <X0> IS{start} <X2> ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+        kb.tell(&f2).unwrap();
         let f3 = format!("animal IS{start} thing ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f3.as_str() ) });
+        kb.tell(&f3).unwrap();
         let f4 = format!("mammal IS{start} animal ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f4.as_str() ) });
+        kb.tell(&f4).unwrap();
         let f5 = format!("primate IS{start} mammal ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f5.as_str() ) });
+        kb.tell(&f5).unwrap();
         let f6 = format!("human IS{start} primate ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f6.as_str() ) });
+        kb.tell(&f6).unwrap();
         for i in 0..opt.facts {
             let s = sets[(i % nsets) as usize];
             let name = format!("{}{}{}", s, i, start);
-            let f = Box::leak(Box::new(format!("{name} ISA{start} {s} ◊", name = name, start = start, s = s)));
-            kb.tell( unsafe { mem::transmute( f.as_str() ) });
+            let f = format!("{name} ISA{start} {s} ◊", name = name, start = start, s = s);
+            kb.tell(&f).unwrap();
         }
         if ((r % opt.report) == 0) || (r + 1 == opt.rules) {
             for _s in 0..opt.samples {
@@ -105,25 +104,25 @@ fn main() {
                     let t0 = SystemTime::now();
 
                     let f1 = format!("<X0> ISA{start} <X1> -> <X1> IS{start} <X2> -> <X0> ISA{start} <X2> ◊", start = start);
-                    kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+                    kb.tell(&f1).unwrap();
                     let f2 = format!("<X0> IS{start} <X1> -> <X1> IS{start} <X2> -> <X0> IS{start} <X2> ◊", start = start);
-                    kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+                    kb.tell(&f2).unwrap();
 
                     let t1 = SystemTime::now();
 
                     let f3 = format!("animal IS{start} thing ◊", start = start);
-                    kb.tell( unsafe { mem::transmute( f3.as_str() ) });
+                    kb.tell(&f3).unwrap();
                     let f4 = format!("mammal IS{start} animal ◊", start = start);
-                    kb.tell( unsafe { mem::transmute( f4.as_str() ) });
+                    kb.tell(&f4).unwrap();
                     let f5 = format!("primate IS{start} mammal ◊", start = start);
-                    kb.tell( unsafe { mem::transmute( f5.as_str() ) });
+                    kb.tell(&f5).unwrap();
                     let f6 = format!("human IS{start} primate ◊", start = start);
-                    kb.tell( unsafe { mem::transmute( f6.as_str() ) });
+                    kb.tell(&f6).unwrap();
                     for i in 0..opt.facts {
                         let s = sets[(i % nsets) as usize];
                         let name = format!("{}{}{}", s, i, start);
-                        let f = Box::leak(Box::new(format!("{name} ISA{start} {s} ◊", name = name, start = start, s = s)));
-                        kb.tell( unsafe { mem::transmute( f.as_str() ) });
+                        let f = format!("{name} ISA{start} {s} ◊", name = name, start = start, s = s);
+                        kb.tell(&f).unwrap();
                     }
                     let t2 = SystemTime::now();
 
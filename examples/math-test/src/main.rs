@@ -45,7 +45,7 @@ fn main() {
             }?}
             → <X1> ∈ N ◊
 
-    ");
+    ").unwrap();
 
-    kb.tell("0 ∈ N ◊");
+    kb.tell("0 ∈ N ◊").unwrap();
 }
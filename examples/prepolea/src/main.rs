@@ -48,18 +48,18 @@ fn main() {
              (fn: (fn: <X1>, on: <X5>), on: <X8>) EQ <X9>
               -> 
              (p1: <X7>, p2: <X9>) ISA (fn: <X1>, on: (p1: <X2>, p2: <X3>));
-             (fn: (fn: <X1>, on: (p1: <X4>, p2: <X5>)), on: (p1: <X6>, p2: <X8>)) EQ (p1: <X7>, p2: <X9>)<>");
+             (fn: (fn: <X1>, on: (p1: <X4>, p2: <X5>)), on: (p1: <X6>, p2: <X8>)) EQ (p1: <X7>, p2: <X9>)<>").unwrap();
      kb.tell("(p1: <X2>, p2: <X3>) ISA (fn: <X1>, on: (p1: <X4>, p2: <X5>))
             -> 
             <X2> ISA (fn: <X1>, on: <X4>);
-            <X3> ISA (fn: <X1>, on: <X5>)<>");
+            <X3> ISA (fn: <X1>, on: <X5>)<>").unwrap();
      kb.tell("<X1> ISA (fn: pr, on: nat)
             -> 
-            (fn: (fn: pr, on: s1), on: <X1>) EQ (s: <X1>)<>");
-     kb.tell("s2 ISA (hom1: people, hom2: people)<>");
-     kb.tell("(p1: s1, p2: s2) ISA (hom1: (p1: nat, p2: people), hom2: (p1: nat, p2: people))<>");
-     kb.tell("s1 ISA (hom1: nat, hom2: nat)<>");
-     kb.tell("(p1: (s: 0), p2: john) ISA (fn: pr, on: (p1: nat, p2: people))<>");
+            (fn: (fn: pr, on: s1), on: <X1>) EQ (s: <X1>)<>").unwrap();
+     kb.tell("s2 ISA (hom1: people, hom2: people)<>").unwrap();
+     kb.tell("(p1: s1, p2: s2) ISA (hom1: (p1: nat, p2: people), hom2: (p1: nat, p2: people))<>").unwrap();
+     kb.tell("s1 ISA (hom1: nat, hom2: nat)<>").unwrap();
+     kb.tell("(p1: (s: 0), p2: john) ISA (fn: pr, on: (p1: nat, p2: people))<>").unwrap();
      kb.tell("john ISA (fn: pr, on: people)<>
                    susan ISA (fn: pr, on: people)<>
                    sue1 ISA (fn: pr, on: people)<>
@@ -84,7 +84,7 @@ fn main() {
                    ken ISA (fn: pr, on: people)<>
                    bob ISA (fn: pr, on: people)<>
                    isa ISA (fn: pr, on: people)<>
-                   peter ISA (fn: pr, on: people)<>");
+                   peter ISA (fn: pr, on: people)<>").unwrap();
      kb.tell("(fn: (fn: pr, on: s2), on: john) EQ susan<>
                   (fn: (fn: pr, on: s2), on: susan) EQ sue1<>
                   (fn: (fn: pr, on: s2), on: sue1) EQ sue2<>
@@ -108,5 +108,5 @@ fn main() {
                   (fn: (fn: pr, on: s2), on: sue19) EQ ken<>
                   (fn: (fn: pr, on: s2), on: ken) EQ bob<>
                   (fn: (fn: pr, on: s2), on: bob) EQ isa<>
-                  (fn: (fn: pr, on: s2), on: isa) EQ peter<>");
+                  (fn: (fn: pr, on: s2), on: isa) EQ peter<>").unwrap();
 }
@@ -36,8 +36,8 @@ fn main() {
     env_logger::init();
     let kb = kb::KBGenerator::gen_kb();
     kb.tell("<X0> ⊆ <X1> → <X2> ⊆ <X0> → <X2> ⊆ <X1> ◊
-             <X0> ⊆ <X1> → <X2> ∈ <X0> → <X2> ∈ <X1> ◊");
+             <X0> ⊆ <X1> → <X2> ∈ <X0> → <X2> ∈ <X1> ◊").unwrap();
     kb.tell("human ⊆ primate ◊
              primate ⊆ animal ◊
-             susan ∈ human ◊");
+             susan ∈ human ◊").unwrap();
 }
@@ -140,5 +140,5 @@ fn main() {
         animal96 ISA animal.\
         mammal97 ISA mammal.\
         primate98 ISA primate.\
-        human99 ISA human.");
+        human99 ISA human.").unwrap();
 }
@@ -25,7 +25,6 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
-use std::mem;
 use std::time::SystemTime;
 use structopt::StructOpt;
 //use std::{thread, time};
@@ -72,20 +71,20 @@ fn main() {
     for r in 0..opt.rules {
         start += 1;
         let f1 = format!("mammal{start} <X1> -> animal{start} <X1> ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+        kb.tell(&f1).unwrap();
         let f2 = format!("primate{start} <X1> -> mammal{start} <X1> ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+        kb.tell(&f2).unwrap();
         let f3 = format!("human{start} <X1> -> primate{start} <X1> ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f3.as_str() ) });
+        kb.tell(&f3).unwrap();
         let f4 = format!("living{start} <X1> -> animal{start} <X1> -> mortal{start} <X1> ◊", start = start);
-        kb.tell( unsafe { mem::transmute( f4.as_str() ) });
+        kb.tell(&f4).unwrap();
         //num_rules += 4;
         for i in 0..opt.facts {
             let name = format!("socrate{}n{}", start, i);
-            let ff1 = Box::leak(Box::new(format!("human{start} {name} ◊", name = name, start = start)));
-            kb.tell( unsafe { mem::transmute( ff1.as_str() ) });
-            let ff2 = Box::leak(Box::new(format!("living{start} {name} ◊", name = name, start = start)));
-            kb.tell( unsafe { mem::transmute( ff2.as_str() ) });
+            let ff1 = format!("human{start} {name} ◊", name = name, start = start);
+            kb.tell(&ff1).unwrap();
+            let ff2 = format!("living{start} {name} ◊", name = name, start = start);
+            kb.tell(&ff2).unwrap();
             //num_facts += 2;
         }
         if ((r % opt.report) == 0) || (r + 1 == opt.rules) {
@@ -96,13 +95,13 @@ fn main() {
                 //let t_r_1 = SystemTime::now();
 
                 let f1 = format!("mammal{start} <X1> -> animal{start} <X1> ◊", start = start);
-                kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+                kb.tell(&f1).unwrap();
                 let f2 = format!("primate{start} <X1> -> mammal{start} <X1> ◊", start = start);
-                kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+                kb.tell(&f2).unwrap();
                 let f3 = format!("human{start} <X1> -> primate{start} <X1> ◊", start = start);
-                kb.tell( unsafe { mem::transmute( f3.as_str() ) });
+                kb.tell(&f3).unwrap();
                 let f4 = format!("living{start} <X1> -> animal{start} <X1> -> mortal{start} <X1> ◊", start = start);
-                kb.tell( unsafe { mem::transmute( f4.as_str() ) });
+                kb.tell(&f4).unwrap();
                 //num_rules += 4;
 
                 //let t_r_2 = SystemTime::now();
@@ -110,29 +109,29 @@ fn main() {
 
                 for i in 0..(opt.facts - 1) {
                     let name = format!("socrate{}n{}", start, i);
-                    let ff1 = Box::leak(Box::new(format!("human{start} {name} ◊", name = name, start = start)));
-                    kb.tell( unsafe { mem::transmute( ff1.as_str() ) });
-                    let ff2 = Box::leak(Box::new(format!("living{start} {name} ◊", name = name, start = start)));
-                    kb.tell( unsafe { mem::transmute( ff2.as_str() ) });
+                    let ff1 = format!("human{start} {name} ◊", name = name, start = start);
+                    kb.tell(&ff1).unwrap();
+                    let ff2 = format!("living{start} {name} ◊", name = name, start = start);
+                    kb.tell(&ff2).unwrap();
                     //num_facts += 2;
 
-                    let q = Box::leak(Box::new(format!("mortal{start} <X1> ◊", start = start)));
-                    kb.ask( unsafe { mem::transmute( q.as_str() ) });
+                    let q = format!("mortal{start} <X1> ◊", start = start);
+                    kb.ask(&q).unwrap();
                 }
 
                 let name = format!("socrate{}", start);
                 //let t_f_1 = SystemTime::now();
-                let ff1 = Box::leak(Box::new(format!("human{start} {name} ◊", name = name, start = start)));
-                kb.tell( unsafe { mem::transmute( ff1.as_str() ) });
-                let ff2 = Box::leak(Box::new(format!("living{start} {name} ◊", name = name, start = start)));
-                kb.tell( unsafe { mem::transmute( ff2.as_str() ) });
+                let ff1 = format!("human{start} {name} ◊", name = name, start = start);
+                kb.tell(&ff1).unwrap();
+                let ff2 = format!("living{start} {name} ◊", name = name, start = start);
+                kb.tell(&ff2).unwrap();
                 //let t_f_2 = SystemTime::now();
                 //let f_time = t_f_2.duration_since(t_f_1).unwrap().as_micros() as f64 / 2.0;
                 //num_facts += 2;
 
                 let q_1 = SystemTime::now();
-                let q = Box::leak(Box::new(format!("mortal{start} <X1> ◊", start = start)));
-                let res = kb.ask( unsafe { mem::transmute( q.as_str() ) });
+                let q = format!("mortal{start} <X1> ◊", start = start);
+                let res = kb.ask(&q).unwrap();
                 let q_2 = SystemTime::now();
                 let num_results = res.len();
 
@@ -25,7 +25,6 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
-use std::mem;
 use std::time::SystemTime;
 use structopt::StructOpt;
 //use std::{thread, time};
@@ -72,7 +71,7 @@ fn main() {
 
     //let o_one_sec = time::Duration::from_millis(100);
     //let t0 = SystemTime::now();
-    kb.tell("animal <X1> -> living <X1> -> mortal <X1> ◊");
+    kb.tell("animal <X1> -> living <X1> -> mortal <X1> ◊").unwrap();
     let mut animal = String::from("animal");
     let mut living = String::from("living");
     
@@ -81,26 +80,26 @@ fn main() {
         let living_next = format!("living{d}", d=d);
 
         let f1 = format!("{animal_next} <X1> -> {animal} <X1> ◊", animal=animal, animal_next=animal_next);
-        kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+        kb.tell(&f1).unwrap();
 
         let f2 = format!("{living_next} <X1> -> {living} <X1> ◊", living=living, living_next=living_next);
-        kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+        kb.tell(&f2).unwrap();
 
         for g in 0..opt.garbage {
             let thingy = format!("thing{d}n{g}", d=d, g=g);
             let thongy = format!("thong{d}n{g}", d=d, g=g);
 
             let f1 = format!("{thingy} <X1> -> pre{thingy} <X1> ◊", thingy=thingy);
-            kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+            kb.tell(&f1).unwrap();
 
             let f2 = format!("{thingy} lattle{thingy} ◊", thingy=thingy);
-            kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+            kb.tell(&f2).unwrap();
 
             let f3 = format!("{thongy} <X1> -> pre{thongy} <X1> ◊", thongy=thongy);
-            kb.tell( unsafe { mem::transmute( f3.as_str() ) });
+            kb.tell(&f3).unwrap();
 
             let f4 = format!("{thongy} lattle{thingy} ◊", thongy=thongy, thingy=thingy);
-            kb.tell( unsafe { mem::transmute( f4.as_str() ) });
+            kb.tell(&f4).unwrap();
         }
 
         for g in 0..opt.rgarbage {
@@ -108,16 +107,16 @@ fn main() {
             let thongy = format!("thong{d}n{g}", d=d, g=g);
 
             let f1 = format!("{thingy} <X1> -> {animal} <X1> ◊", thingy=thingy, animal=animal);
-            kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+            kb.tell(&f1).unwrap();
 
             let f2 = format!("{thingy} little{thingy} ◊", thingy=thingy);
-            kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+            kb.tell(&f2).unwrap();
 
             let f3 = format!("{thongy} <X1> -> {living} <X1> ◊", thongy=thongy, living=living);
-            kb.tell( unsafe { mem::transmute( f3.as_str() ) });
+            kb.tell(&f3).unwrap();
 
             let f4 = format!("{thongy} little{thongy} ◊", thongy=thongy);
-            kb.tell( unsafe { mem::transmute( f4.as_str() ) });
+            kb.tell(&f4).unwrap();
         }
         animal = String::from(animal_next);
         living = String::from(living_next);
@@ -127,10 +126,10 @@ fn main() {
         let mortal = format!("mortal{n}", n=n);
 
         let f1 = format!("{animal} {mortal} ◊", animal=animal, mortal=mortal);
-        kb.tell( unsafe { mem::transmute( f1.as_str() ) });
+        kb.tell(&f1).unwrap();
 
         let f2 = format!("{living} {mortal} ◊", living=living, mortal=mortal);
-        kb.tell( unsafe { mem::transmute( f2.as_str() ) });
+        kb.tell(&f2).unwrap();
     }
 
     let mut query_mean: f64 = 0.0;
@@ -138,7 +137,7 @@ fn main() {
 
     for _q in 0..opt.queries {
         let t_1 = SystemTime::now();
-        let res = kb.ask("mortal <X1> ◊");
+        let res = kb.ask("mortal <X1> ◊").unwrap();
         let t_2 = SystemTime::now();
         num_results = res.len();
         query_mean += t_2.duration_since(t_1).unwrap().as_micros() as f64;
@@ -17,9 +17,13 @@
 // along with any part of the modus_ponens project.    
 // If not, see <http://www.gnu.org/licenses/>.
 
-use crate::ruletree::MPRule;
+use std::fmt;
+
+use crate::ruletree::{ MPRule, RuleId };
+use crate::bindings::Bindings;
 use crate::matching::MPMatching;
 use crate::path::MPPath;
+use crate::tms::FactKey;
 
 pub struct ParseResult<'a> {
     pub facts: Vec<&'a str>,
@@ -37,6 +41,9 @@ pub enum Activation<'a> {
     Fact {
         fact: &'a str,
         matched: Option<MPMatching<'a>>,
+        support: Option<Vec<FactKey>>,
+        /// The source and name of the rule that derived the fact.
+        rule: Option<(&'a str, Option<&'a str>)>,
         query_rules: bool,
     },
     Match {
@@ -44,14 +51,21 @@ pub enum Activation<'a> {
         matched: Option<MPMatching<'a>>,
         query_rules: bool,
     },
+    /// A rule with all its antecedents matched, in the agenda.
+    Firing {
+        rule: MPRule<'a>,
+        query_rules: bool,
+    },
 }
 
 impl<'a> Activation<'a> {
 
-    pub fn from_fact(fact: &'a str, matched: Option<MPMatching<'a>>, query_rules: bool) -> Activation<'a> {
+    pub fn from_fact(fact: &'a str, matched: Option<MPMatching<'a>>, support: Option<Vec<FactKey>>, rule: Option<(&'a str, Option<&'a str>)>, query_rules: bool) -> Activation<'a> {
         Activation::Fact {
             fact,
             matched,
+            support,
+            rule,
             query_rules,
         }
     }
@@ -69,4 +83,42 @@ impl<'a> Activation<'a> {
             query_rules,
         }
     }
+    pub fn from_firing(rule: MPRule<'a>, query_rules: bool) -> Activation<'a> {
+        Activation::Firing {
+            rule,
+            query_rules,
+        }
+    }
+    /// The id of the rule that is added, matched or fired, if any.
+    pub fn rule_id(&self) -> Option<RuleId> {
+        match self {
+            Activation::MPRule { rule, .. } => Some(rule.id),
+            Activation::Match { rule, .. } => Some(rule.id),
+            Activation::Firing { rule, .. } => Some(rule.id),
+            Activation::Fact { .. } => None,
+        }
+    }
+}
+
+/// A one line description of the activation, for debugging.
+impl<'a> fmt::Display for Activation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Activation::MPRule { rule, .. } => {
+                let remaining = rule.more_antecedents.len() + rule.antecedents.fact.map_or(0, |_| 1);
+                write!(f, "add rule {} (antecedents left: {})", rule.label(), remaining)
+            },
+            Activation::Fact { fact, matched: Some(matched), .. } if !matched.is_empty() => {
+                write!(f, "add fact {} with {}", fact, Bindings::from_matching(matched))
+            },
+            Activation::Fact { fact, .. } => write!(f, "add fact {}", fact),
+            Activation::Match { rule, matched: Some(matched), .. } => {
+                write!(f, "match rule {} with {}", rule.label(), Bindings::from_matching(matched))
+            },
+            Activation::Match { rule, .. } => write!(f, "match rule {}", rule.label()),
+            Activation::Firing { rule, .. } => {
+                write!(f, "fire rule {} with {}", rule.label(), Bindings::from_matching(&rule.matched))
+            },
+        }
+    }
 }
@@ -0,0 +1,186 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! The agenda of rules whose antecedents have all been matched, waiting to fire.
+//!
+//! Rules fire in order of salience, given with `@salience(...)` (0 by default),
+//! and rules with the same salience in the order prescribed by the strategy
+//! of the knowledge base.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io::Read;
+
+use crate::activation::Activation;
+use crate::snapshot::{ Encoder, Decoder, SnapshotError };
+
+
+/// How to order rules with the same salience in the agenda.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// The most recently activated rules fire first.
+    Depth,
+    /// Rules fire in the order in which they were activated.
+    #[default]
+    Breadth,
+    /// Rules matched with the most recently added facts fire first,
+    /// comparing their facts from the newest down.
+    Recency,
+    /// Rules with more antecedents and conditions fire first,
+    /// and the most recently activated among those that have as many.
+    Specificity,
+}
+
+struct Pending<'a> {
+    salience: i32,
+    specificity: usize,
+    times: Vec<u64>,
+    order: u64,
+    /// The number of activations pushed before this one.
+    seq: u64,
+    activation: Activation<'a>,
+}
+
+impl<'a> Pending<'a> {
+    fn key(&self) -> (i32, usize, &[u64], u64) {
+        (self.salience, self.specificity, &self.times, self.order)
+    }
+}
+
+impl<'a> PartialEq for Pending<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+impl<'a> Eq for Pending<'a> {}
+impl<'a> PartialOrd for Pending<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<'a> Ord for Pending<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Activations of rules waiting to fire, the first to fire on top.
+pub struct Agenda<'a> {
+    pending: BinaryHeap<Pending<'a>>,
+    activated: u64,
+}
+
+impl<'a> Agenda<'a> {
+    pub fn new() -> Agenda<'a> {
+        Agenda {
+            pending: BinaryHeap::new(),
+            activated: 0,
+        }
+    }
+    /// Add an activation of a rule with the given salience and specificity.
+    /// `times` are the times at which the facts that matched the rule were added,
+    /// only used with the recency strategy.
+    pub fn push(&mut self, strategy: Strategy, salience: i32, specificity: usize, mut times: Vec<u64>, activation: Activation<'a>) {
+        let seq = self.activated;
+        self.activated += 1;
+        let order = match strategy {
+            Strategy::Breadth => u64::MAX - self.activated,
+            _ => self.activated,
+        };
+        let specificity = match strategy {
+            Strategy::Specificity => specificity,
+            _ => 0,
+        };
+        match strategy {
+            Strategy::Recency => times.sort_unstable_by(|a, b| b.cmp(a)),
+            _ => times.clear(),
+        }
+        self.pending.push(Pending { salience, specificity, times, order, seq, activation });
+    }
+    /// Take the activation that has to fire next.
+    pub fn pop(&mut self) -> Option<Activation<'a>> {
+        self.pending.pop().map(|pending| pending.activation)
+    }
+    /// The activations waiting, in the order in which they will fire.
+    pub fn activations(&self) -> Vec<&Activation<'a>> {
+        let mut pending: Vec<&Pending<'a>> = self.pending.iter().collect();
+        pending.sort_by(|a, b| b.cmp(a));
+        pending.into_iter().map(|pending| &pending.activation).collect()
+    }
+    /// The number of activations ever pushed.
+    pub fn activated(&self) -> u64 {
+        self.activated
+    }
+    /// The activations waiting that were pushed after the first `activated`,
+    /// in the order in which they were pushed.
+    pub fn pushed_after(&self, activated: u64) -> Vec<&Activation<'a>> {
+        let mut pending: Vec<&Pending<'a>> = self.pending.iter().filter(|pending| pending.seq >= activated).collect();
+        pending.sort_by_key(|pending| pending.seq);
+        pending.into_iter().map(|pending| &pending.activation).collect()
+    }
+    /// Keep only the activations for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Activation<'a>) -> bool) {
+        self.pending.retain(|pending| keep(&pending.activation));
+    }
+    /// Write the activations waiting to a snapshot, with the keys that order them,
+    /// using `save_activation` for the activations themselves.
+    pub fn save(&self, enc: &mut Encoder<'a>, mut save_activation: impl FnMut(&Activation<'a>, &mut Encoder<'a>)) {
+        enc.u64(self.activated);
+        enc.count(self.pending.len());
+        for pending in self.pending.iter() {
+            enc.u32(pending.salience as u32);
+            enc.count(pending.specificity);
+            enc.keys(&pending.times);
+            enc.u64(pending.order);
+            enc.u64(pending.seq);
+            save_activation(&pending.activation, enc);
+        }
+    }
+    /// Read an agenda written by `save`.
+    pub fn restore<R: Read>(
+        dec: &mut Decoder<'a, R>,
+        mut restore_activation: impl FnMut(&mut Decoder<'a, R>) -> Result<Activation<'a>, SnapshotError>,
+    ) -> Result<Agenda<'a>, SnapshotError> {
+        let mut agenda = Agenda::new();
+        agenda.activated = dec.u64()?;
+        let n = dec.count()?;
+        for _ in 0..n {
+            let salience = dec.u32()? as i32;
+            let specificity = dec.count()?;
+            let times = dec.keys()?;
+            let order = dec.u64()?;
+            let seq = dec.u64()?;
+            let activation = restore_activation(dec)?;
+            agenda.pending.push(Pending { salience, specificity, times, order, seq, activation });
+        }
+        Ok(agenda)
+    }
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<'a> Default for Agenda<'a> {
+    fn default() -> Self {
+        Agenda::new()
+    }
+}
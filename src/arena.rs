@@ -0,0 +1,71 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+
+
+/// Storage for the nodes of the fact and rule trees.
+///
+/// Values are boxed so that they do not move when more values are added,
+/// and they are only freed when the arena is dropped;
+/// so references to them can be handed out for as long as the arena lives.
+pub struct Arena<T>(RefCell<Vec<Box<T>>>);
+
+impl<T> Arena<T> {
+    pub fn new() -> Arena<T> {
+        Arena(RefCell::new(vec![]))
+    }
+    pub fn alloc(&self, value: T) -> &T {
+        let boxed = Box::new(value);
+        let value_ref: *const T = &*boxed;
+        self.0.borrow_mut().push(boxed);
+        unsafe { &*value_ref }
+    }
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Arena::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_values_dropped_with_arena() {
+        let counter = Rc::new(());
+        let arena = Arena::new();
+        for _ in 0..3 {
+            arena.alloc(counter.clone());
+        }
+        assert_eq!(arena.len(), 3);
+        assert_eq!(Rc::strong_count(&counter), 4);
+        drop(arena);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+}
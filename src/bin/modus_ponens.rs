@@ -0,0 +1,65 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! An interactive shell for a knowledge base with the grammar in a pest file.
+//!
+//! Usage: modus_ponens GRAMMAR.pest [KNOWLEDGE.mp ...]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+use modus_ponens::grammar::Grammar;
+use modus_ponens::knowledge::KnowledgeBase;
+use modus_ponens::repl;
+
+
+fn main() {
+    env_logger::init();
+    let mut args = env::args().skip(1);
+    let grammar_file = match args.next() {
+        Some(file) if file != "-h" && file != "--help" => file,
+        _ => {
+            eprintln!("usage: modus_ponens GRAMMAR.pest [KNOWLEDGE.mp ...]");
+            process::exit(2);
+        },
+    };
+    let source = fs::read_to_string(&grammar_file).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {}", grammar_file, err);
+        process::exit(1);
+    });
+    let grammar = Grammar::new(&source).unwrap_or_else(|err| {
+        eprintln!("in {}: {}", grammar_file, err);
+        process::exit(1);
+    });
+    let kb = KnowledgeBase::new(grammar);
+    kb.record_provenance(true);
+    for file in args {
+        if let Err(err) = kb.tell_file(&file) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+    let stdin = io::stdin();
+    if let Err(err) = repl::run(&kb, stdin.lock(), io::stdout()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::slice;
+use std::vec;
+
+use crate::matching::MPMatching;
+
+
+/// An assignment of values to variables, as returned in responses to queries.
+///
+/// It owns its strings, so it does not borrow from the knowledge base.
+/// The variables are kept sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Bindings {
+    pairs: Vec<(String, String)>,
+}
+
+impl Bindings {
+    pub fn from_matching(matching: &MPMatching) -> Bindings {
+        let mut pairs: Vec<(String, String)> = matching.iter()
+            .map(|(var, val)| (var.text.clone(), val.text.clone()))
+            .collect();
+        pairs.sort();
+        Bindings { pairs }
+    }
+    /// The value bound to a variable, given with its delimiters, e.g. `"<X1>"`.
+    pub fn get(&self, var: &str) -> Option<&str> {
+        match self.pairs.binary_search_by(|(v, _)| v.as_str().cmp(var)) {
+            Ok(i) => Some(self.pairs[i].1.as_str()),
+            Err(_) => None,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+    /// Pairs of variable and value, ordered by variable.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(var, val)| (var.as_str(), val.as_str()))
+    }
+    pub fn into_map(self) -> HashMap<String, String> {
+        self.pairs.into_iter().collect()
+    }
+}
+
+impl From<Bindings> for HashMap<String, String> {
+    fn from(bindings: Bindings) -> HashMap<String, String> {
+        bindings.into_map()
+    }
+}
+
+impl IntoIterator for Bindings {
+    type Item = (String, String);
+    type IntoIter = vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pairs.into_iter()
+    }
+}
+
+impl<'b> IntoIterator for &'b Bindings {
+    type Item = &'b (String, String);
+    type IntoIter = slice::Iter<'b, (String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pairs.iter()
+    }
+}
+
+impl fmt::Display for Bindings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = self.iter()
+            .map(|(var, val)| format!("{}: {}", var, val))
+            .collect();
+        write!(f, "{{{}}}", pairs.join(", "))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexicon::Lexicon;
+
+    #[test]
+    fn test_bindings_from_matching() {
+        let lexicon = Lexicon::new();
+        let mut matching: MPMatching = HashMap::new();
+        matching.insert(lexicon.intern("var", "<X2>", true), lexicon.intern("v_name", "susan", true));
+        matching.insert(lexicon.intern("var", "<X1>", true), lexicon.intern("v_name", "human", true));
+        let bindings = Bindings::from_matching(&matching);
+        assert_eq!(bindings.get("<X1>"), Some("human"));
+        assert_eq!(bindings.get("<X3>"), None);
+        let vars: Vec<&str> = bindings.iter().map(|(var, _)| var).collect();
+        assert_eq!(vars, vec!["<X1>", "<X2>"]);
+        assert_eq!(bindings.to_string(), "{<X1>: human, <X2>: susan}");
+        let map: HashMap<String, String> = bindings.into();
+        assert_eq!(map.get("<X2>").map(String::as_str), Some("susan"));
+    }
+}
@@ -1,83 +1,141 @@
+use std::collections::HashSet;
+
 use pest::Parser;
 //use log::debug;
 
+use crate::error::{ MPError, line_col };
 use crate::lexicon::Lexicon;
 use crate::matching::MPMatching;
+use crate::segment::MPSegment;
 
 #[derive(Parser)]
 #[grammar = "condition.pest"]
 pub struct CParser;
 
-impl<'a> CParser {
+/// A term in a condition. Variables keep their byte offset
+/// in the source of the conditions, to report errors.
+#[derive(Debug)]
+pub enum Term<'a> {
+    Number(f64),
+    Text(&'a str),
+    Var(&'a MPSegment, usize),
+}
 
-    pub fn check_conditions(source: &str, matching: &MPMatching<'a>, lexicon: &'a Lexicon) -> bool {
+#[derive(Debug)]
+pub enum Condition<'a> {
+    Num(Term<'a>, &'a str, Term<'a>),
+    Str(Term<'a>, &'a str, Term<'a>),
+}
 
-        let parse_result = CParser::parse(Rule::conditions, source);
-        if parse_result.is_err() {
-            panic!("These do not seem like conditions: \"{}\"\n\nerr: {}\n\nmatching: {:?}", source, parse_result.err().unwrap(), matching);
-        }
-        let mut pairs = parse_result.ok().unwrap();
+/// The compiled conditions section of an antecedent.
+#[derive(Debug)]
+pub struct Conditions<'a> {
+    pub source: &'a str,
+    pub conditions: Vec<Condition<'a>>,
+}
+
+impl<'a> CParser {
 
+    /// Compile a conditions section,
+    /// checking that the variables used in it are in `bound`.
+    pub fn compile(source: &'a str, lexicon: &'a Lexicon, bound: &HashSet<String>) -> Result<Conditions<'a>, MPError> {
+        let mut pairs = match CParser::parse(Rule::conditions, source) {
+            Ok(pairs) => pairs,
+            Err(err) => return Err(MPError::condition(source, err)),
+        };
+        let mut conditions = vec![];
         for pair in pairs.next().unwrap().into_inner() {
             let mut exprpair = pair.into_inner();
             let t1pair = exprpair.next().expect("1st term");
-
             let pred = exprpair.next().expect("the condition's pred");
-            match pred.as_rule() {
-                Rule::num_pred => {
-                    let val1 = CParser::compile_num(t1pair, matching, lexicon);
-                    let t2pair = exprpair.next().expect("2st term");
-                    let val2 = CParser::compile_num(t2pair, matching, lexicon);
-
-                    let pass = eval_num_condition(val1, pred.as_str(), val2);
-                    if !pass {
-                        return false;
-                    }
-                },
-                Rule::str_pred => {
-                    let val1 = CParser::compile_str(t1pair, matching, lexicon);
-                    let t2pair = exprpair.next().expect("2st term");
-                    let val2 = CParser::compile_str(t2pair, matching, lexicon);
-
-                    let pass = eval_str_condition(val1, pred.as_str(), val2);
-                    if !pass {
-                        return false;
-                    }
-                },
+            let t2pair = exprpair.next().expect("2st term");
+            let is_num = match pred.as_rule() {
+                Rule::num_pred => true,
+                Rule::str_pred => false,
                 unknown_pred => panic!("Unexpected predicate: {:?}", unknown_pred),
+            };
+            let t1 = CParser::compile_term(source, t1pair, is_num, lexicon, bound)?;
+            let t2 = CParser::compile_term(source, t2pair, is_num, lexicon, bound)?;
+            if is_num {
+                conditions.push(Condition::Num(t1, pred.as_str(), t2));
+            } else {
+                conditions.push(Condition::Str(t1, pred.as_str(), t2));
             }
         }
-        true
+        Ok(Conditions { source, conditions })
     }
 
-    fn compile_num(pair: pest::iterators::Pair<Rule>, matching: &MPMatching<'a>, lexicon: &Lexicon) -> f64 {
+    fn compile_term(source: &str, pair: pest::iterators::Pair<'a, Rule>, is_num: bool, lexicon: &'a Lexicon, bound: &HashSet<String>) -> Result<Term<'a>, MPError> {
         match pair.as_rule() {
-            Rule::v_decimal => {
-                pair.as_str().parse::<f64>().ok().expect("a number")
+            Rule::v_decimal if is_num => {
+                Ok(Term::Number(pair.as_str().parse::<f64>().expect("a number")))
+            },
+            Rule::v_string if is_num => {
+                let pos = pair.as_span().start_pos().line_col();
+                Err(MPError::bad_condition(source, pos, format!("\"{}\" is not a number", pair.as_str())))
+            },
+            Rule::v_string | Rule::v_decimal => {
+                Ok(Term::Text(pair.as_str()))
             },
             Rule::var => {
-                let var = lexicon.intern("var", pair.as_str(), true);
-                let number = matching.get(var).expect("number segment");
-                let result = number.text.parse::<f64>();
-                if result.is_err() {
-                    panic!("This does not seem like a number: \"{}\"\n\nerr: {}\n\nmatching: {:?}", number.text, result.err().unwrap(), matching);
+                if !bound.contains(pair.as_str()) {
+                    let pos = pair.as_span().start_pos().line_col();
+                    return Err(MPError::bad_condition(source, pos, format!("variable {} is not bound", pair.as_str())));
                 }
-                result.ok().unwrap()
+                Ok(Term::Var(lexicon.intern("var", pair.as_str(), true), pair.as_span().start()))
             },
             unknown_expr => panic!("Unexpected expression: {:?}", unknown_expr),
         }
     }
 
-    fn compile_str(pair: pest::iterators::Pair<'a, Rule>, matching: &'a MPMatching<'a>, lexicon: &'a Lexicon) -> &'a str {
-        match pair.as_rule() {
-            Rule::v_string => {
-                pair.as_str()
-            },
-            Rule::var => {
-                let var = lexicon.intern("var", pair.as_str(), true);
-                &matching.get(var).expect("number segment").text
+    pub fn check_conditions(conditions: &Conditions<'a>, matching: &MPMatching<'a>) -> Result<bool, MPError> {
+        let source = conditions.source;
+        for condition in conditions.conditions.iter() {
+            let pass = match condition {
+                Condition::Num(t1, pred, t2) => {
+                    let val1 = CParser::eval_num(source, t1, matching)?;
+                    let val2 = CParser::eval_num(source, t2, matching)?;
+                    eval_num_condition(val1, pred, val2)
+                },
+                Condition::Str(t1, pred, t2) => {
+                    let val1 = CParser::eval_str(source, t1, matching)?;
+                    let val2 = CParser::eval_str(source, t2, matching)?;
+                    eval_str_condition(val1, pred, val2)
+                },
+            };
+            if !pass {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn eval_num(source: &str, term: &Term<'a>, matching: &MPMatching<'a>) -> Result<f64, MPError> {
+        match term {
+            Term::Number(num) => Ok(*num),
+            Term::Var(var, offset) => {
+                let number = CParser::get_var(source, var, *offset, matching)?;
+                match number.text.parse::<f64>() {
+                    Ok(num) => Ok(num),
+                    Err(_) => Err(MPError::bad_condition(source, line_col(source, *offset), format!("\"{}\", bound to {}, is not a number", number.text, var.text))),
+                }
             },
-            unknown_expr => panic!("Unexpected expression: {:?}", unknown_expr),
+            Term::Text(text) => panic!("Unexpected text in numeric condition: {}", text),
+        }
+    }
+
+    fn eval_str(source: &str, term: &Term<'a>, matching: &MPMatching<'a>) -> Result<&'a str, MPError> {
+        match term {
+            Term::Text(text) => Ok(text),
+            Term::Var(var, offset) => Ok(&CParser::get_var(source, var, *offset, matching)?.text),
+            Term::Number(num) => panic!("Unexpected number in string condition: {}", num),
+        }
+    }
+
+    fn get_var(source: &str, var: &MPSegment, offset: usize, matching: &MPMatching<'a>) -> Result<&'a MPSegment, MPError> {
+        match matching.get(var) {
+            Some(segment) => Ok(segment),
+            None => Err(MPError::bad_condition(source, line_col(source, offset), format!("variable {} is not bound", var.text))),
         }
     }
 }
@@ -96,7 +154,7 @@ fn eval_num_condition(lhs: f64, op: &str, rhs: f64) -> bool {
 
 fn eval_str_condition(lhs: &str, op: &str, rhs: &str) -> bool {
     match op {
-        "eq=" => lhs == rhs,
+        "eq" => lhs == rhs,
         "neq" => lhs != rhs,
         "contains" => lhs.contains(rhs),
         "startswith" => lhs.starts_with(rhs),
@@ -104,3 +162,37 @@ fn eval_str_condition(lhs: &str, op: &str, rhs: &str) -> bool {
         _ => panic!("Unexpected dyadic operator: {}", op),
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_failing_conditions() {
+        let lexicon = Lexicon::new();
+        let mut matching: MPMatching = HashMap::new();
+        matching.insert(lexicon.intern("var", "<X1>", true), lexicon.intern("v_name", "john", true));
+        matching.insert(lexicon.intern("var", "<X2>", true), lexicon.intern("v_decimal", "3", true));
+        let mut bound = HashSet::new();
+        bound.insert(String::from("<X1>"));
+        bound.insert(String::from("<X2>"));
+
+        let conditions = CParser::compile("<X2> > 2", &lexicon, &bound).unwrap();
+        assert_eq!(CParser::check_conditions(&conditions, &matching), Ok(true));
+        let conditions = CParser::compile("<X1> eq john", &lexicon, &bound).unwrap();
+        assert_eq!(CParser::check_conditions(&conditions, &matching), Ok(true));
+
+        let conditions = CParser::compile("<X2> > 2; <X1> > 2", &lexicon, &bound).unwrap();
+        let err = CParser::check_conditions(&conditions, &matching).err().unwrap();
+        assert_eq!(err.position(), (1, 11));
+        let err = CParser::compile("<X3> > 2", &lexicon, &bound).err().unwrap();
+        assert_eq!(err.message(), "variable <X3> is not bound");
+        let err = CParser::compile("<X2> >> 2", &lexicon, &bound).err().unwrap();
+        match err {
+            MPError::Condition { .. } => {},
+            _ => panic!("wrong kind of error"),
+        }
+    }
+}
@@ -23,3 +23,8 @@ pub const UNIQUE_PREFIX: &str = "v_u_";
 pub const NODE_MAP_CAPACITY: usize = 3;
 
 pub const NEW_RULES: bool = false;
+
+/// The number of facts read, journaled and added at a time in bulk loads.
+pub const BULK_CHUNK: usize = 10_000;
+/// The number of activations that bulk loads let wait before running inference.
+pub const BULK_QUEUE: usize = 100_000;
@@ -0,0 +1,282 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use pest::RuleType;
+use pest::error::{ Error as PestError, ErrorVariant, LineColLocation };
+
+use crate::bindings::Bindings;
+use crate::matching::MPMatching;
+
+
+/// Errors produced by knowledge bases.
+///
+/// Every variant carries the offending text, and the line and column
+/// (both starting at 1) within that text where the problem was found;
+/// `InFile` wraps an error found in a file, positioned within the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MPError {
+    /// The text does not follow the modus_ponens syntax for facts and rules.
+    KnowledgeSyntax { text: String, line: usize, col: usize, message: String },
+    /// A fact does not follow the grammar provided by the user.
+    FactSyntax { text: String, line: usize, col: usize, message: String },
+    /// A `{={ ... }=}` section could not be parsed or evaluated.
+    Transform { text: String, line: usize, col: usize, message: String },
+    /// A `{?{ ... }?}` section could not be parsed or evaluated.
+    Condition { text: String, line: usize, col: usize, message: String },
+    /// A grammar loaded at runtime is not a valid pest grammar for facts.
+    Grammar { text: String, line: usize, col: usize, message: String },
+    /// The text could not be written to the journal of the knowledge base,
+    /// so it was not processed.
+    Journal { text: String, line: usize, col: usize, message: String },
+    /// The knowledge could not be read from its source.
+    Read { text: String, line: usize, col: usize, message: String },
+    InFile { file: PathBuf, error: Box<MPError> },
+}
+
+impl MPError {
+    pub fn knowledge<R: RuleType>(text: &str, err: PestError<R>) -> MPError {
+        let (line, col, message) = describe(err);
+        MPError::KnowledgeSyntax { text: text.to_string(), line, col, message }
+    }
+    pub fn fact<R: RuleType>(text: &str, err: PestError<R>) -> MPError {
+        let (line, col, message) = describe(err);
+        MPError::FactSyntax { text: text.to_string(), line, col, message }
+    }
+    pub fn transform<R: RuleType>(text: &str, err: PestError<R>) -> MPError {
+        let (line, col, message) = describe(err);
+        MPError::Transform { text: text.to_string(), line, col, message }
+    }
+    pub fn condition<R: RuleType>(text: &str, err: PestError<R>) -> MPError {
+        let (line, col, message) = describe(err);
+        MPError::Condition { text: text.to_string(), line, col, message }
+    }
+    pub fn grammar<R: RuleType>(text: &str, err: PestError<R>) -> MPError {
+        let (line, col, message) = describe(err);
+        MPError::Grammar { text: text.to_string(), line, col, message }
+    }
+    pub fn journal(text: &str, err: std::io::Error) -> MPError {
+        MPError::Journal { text: text.to_string(), line: 1, col: 1, message: err.to_string() }
+    }
+    /// An error reading knowledge, after reading the given number of lines.
+    pub fn read(err: std::io::Error, line: usize) -> MPError {
+        MPError::Read { text: String::new(), line, col: 1, message: err.to_string() }
+    }
+    pub fn bad_transform(text: &str, (line, col): (usize, usize), message: String) -> MPError {
+        MPError::Transform { text: text.to_string(), line, col, message }
+    }
+    pub fn bad_condition(text: &str, (line, col): (usize, usize), message: String) -> MPError {
+        MPError::Condition { text: text.to_string(), line, col, message }
+    }
+    /// Relocate an error found in a piece of text,
+    /// to be reported against the larger text in which the piece
+    /// starts at byte `offset`.
+    pub fn within(self, outer: &str, offset: usize) -> MPError {
+        let start = line_col(outer, offset);
+        self.relocate(outer.to_string(), start)
+    }
+    /// Relocate an error found in a piece of text that starts
+    /// at the given line and column of a larger input.
+    pub fn shifted(self, start: (usize, usize)) -> MPError {
+        let text = self.text().to_string();
+        self.relocate(text, start)
+    }
+    /// Report the error as found in `file`, unless it was found in an included file.
+    pub fn in_file(self, file: PathBuf) -> MPError {
+        match self {
+            MPError::InFile { .. } => self,
+            error => MPError::InFile { file, error: Box::new(error) },
+        }
+    }
+    fn relocate(self, text: String, (line0, col0): (usize, usize)) -> MPError {
+        // an error in a file is already positioned within it
+        if let MPError::InFile { .. } = self {
+            return self;
+        }
+        let (line, col) = self.position();
+        let col = if line == 1 { col0 + col - 1 } else { col };
+        let line = line0 + line - 1;
+        match self {
+            MPError::KnowledgeSyntax { message, .. } => MPError::KnowledgeSyntax { text, line, col, message },
+            MPError::FactSyntax { message, .. } => MPError::FactSyntax { text, line, col, message },
+            MPError::Transform { message, .. } => MPError::Transform { text, line, col, message },
+            MPError::Condition { message, .. } => MPError::Condition { text, line, col, message },
+            MPError::Grammar { message, .. } => MPError::Grammar { text, line, col, message },
+            MPError::Journal { message, .. } => MPError::Journal { text, line, col, message },
+            MPError::Read { message, .. } => MPError::Read { text, line, col, message },
+            MPError::InFile { .. } => unreachable!("returned above"),
+        }
+    }
+    pub fn text(&self) -> &str {
+        match self {
+            MPError::KnowledgeSyntax { text, .. } => text,
+            MPError::FactSyntax { text, .. } => text,
+            MPError::Transform { text, .. } => text,
+            MPError::Condition { text, .. } => text,
+            MPError::Grammar { text, .. } => text,
+            MPError::Journal { text, .. } => text,
+            MPError::Read { text, .. } => text,
+            MPError::InFile { error, .. } => error.text(),
+        }
+    }
+    pub fn position(&self) -> (usize, usize) {
+        match self {
+            MPError::KnowledgeSyntax { line, col, .. } => (*line, *col),
+            MPError::FactSyntax { line, col, .. } => (*line, *col),
+            MPError::Transform { line, col, .. } => (*line, *col),
+            MPError::Condition { line, col, .. } => (*line, *col),
+            MPError::Grammar { line, col, .. } => (*line, *col),
+            MPError::Journal { line, col, .. } => (*line, *col),
+            MPError::Read { line, col, .. } => (*line, *col),
+            MPError::InFile { error, .. } => error.position(),
+        }
+    }
+    pub fn message(&self) -> &str {
+        match self {
+            MPError::KnowledgeSyntax { message, .. } => message,
+            MPError::FactSyntax { message, .. } => message,
+            MPError::Transform { message, .. } => message,
+            MPError::Condition { message, .. } => message,
+            MPError::Grammar { message, .. } => message,
+            MPError::Journal { message, .. } => message,
+            MPError::Read { message, .. } => message,
+            MPError::InFile { error, .. } => error.message(),
+        }
+    }
+}
+
+impl fmt::Display for MPError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let MPError::InFile { file, error } = self {
+            return write!(f, "in {}: {}", file.display(), error);
+        }
+        let kind = match self {
+            MPError::KnowledgeSyntax { .. } => "knowledge syntax error",
+            MPError::FactSyntax { .. } => "fact syntax error",
+            MPError::Transform { .. } => "transform error",
+            MPError::Condition { .. } => "condition error",
+            MPError::Grammar { .. } => "grammar error",
+            MPError::Journal { .. } => "journal error",
+            MPError::Read { .. } => "read error",
+            MPError::InFile { .. } => unreachable!("displayed above"),
+        };
+        let (line, col) = self.position();
+        write!(f, "{} at {}:{}: {}\n\n{}", kind, line, col, self.message(), self.text())
+    }
+}
+
+impl Error for MPError {}
+
+
+/// A match of a rule that was skipped during inference,
+/// because evaluating its transforms or conditions failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The text of the rule, as it was told.
+    pub rule: String,
+    /// The name of the rule, if it was given one with `@name(...)`.
+    pub name: Option<String>,
+    /// The variable assignments at the moment of the failure.
+    pub matching: Bindings,
+    pub error: MPError,
+}
+
+impl Diagnostic {
+    pub fn new(rule: &str, name: Option<&str>, matching: &MPMatching, error: MPError) -> Diagnostic {
+        Diagnostic {
+            rule: rule.trim().to_string(),
+            name: name.map(String::from),
+            matching: Bindings::from_matching(matching),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "skipped rule {}:", name)?,
+            None => write!(f, "skipped rule:")?,
+        }
+        write!(f, "\n\n{}\n\nwith matching: {}\n\n{}", self.rule, self.matching, self.error)
+    }
+}
+
+
+/// Line and column, starting at 1, of the byte `offset` in `text`.
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let col = before[line_start..].chars().count() + 1;
+    (line, col)
+}
+
+fn describe<R: RuleType>(err: PestError<R>) -> (usize, usize, String) {
+    let (line, col) = match err.line_col {
+        LineColLocation::Pos(pos) => pos,
+        LineColLocation::Span(start, _) => start,
+    };
+    let message = match err.variant {
+        ErrorVariant::ParsingError { positives, negatives } => {
+            match (positives.is_empty(), negatives.is_empty()) {
+                (false, false) => format!("unexpected {:?}, expected {:?}", negatives, positives),
+                (false, true) => format!("expected {:?}", positives),
+                (true, false) => format!("unexpected {:?}", negatives),
+                (true, true) => String::from("unknown parsing error"),
+            }
+        },
+        ErrorVariant::CustomError { message } => message,
+    };
+    (line, col, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pest::Parser;
+    use crate::kparser::{ KParser, Rule };
+
+    #[test]
+    fn test_knowledge_error_position() {
+        let text = "susan ∈ human ◊\nsusan ∈ animal";
+        let err = KParser::parse(Rule::knowledge, text).err().unwrap();
+        let err = MPError::knowledge(text, err);
+        assert_eq!(err.position(), (2, 15));
+        assert_eq!(err.text(), text);
+        match err {
+            MPError::KnowledgeSyntax { .. } => {},
+            _ => panic!("wrong kind of error"),
+        }
+    }
+
+    #[test]
+    fn test_relocate_error() {
+        let rule = "a ∈ <X1> {={ <X2> n= <X3> + 1 }=}\n→ <X2> ∈ b";
+        let err = MPError::bad_transform("<X3> + 1", (1, 1), String::from("variable <X3> is not bound"));
+        let err = err.within(rule, rule.find("<X3>").unwrap());
+        assert_eq!(err.position(), (1, 22));
+        assert_eq!(err.text(), rule);
+        assert_eq!(line_col(rule, rule.find("→").unwrap()), (2, 1));
+    }
+}
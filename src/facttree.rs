@@ -18,10 +18,11 @@
 // If not, see <http://www.gnu.org/licenses/>.
 
 use std::clone::Clone;
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
 use std::cell::{ RefCell };
 use std::mem;
 
+use crate::arena::Arena;
 use crate::constants;
 use crate::path::MPPath;
 use crate::matching::MPMatching;
@@ -46,8 +47,14 @@ pub struct FSNode<'a> {
     lchildren: RefCell<HashMap<MPPath<'a>, &'a FSNode<'a>>>,
 }
 
+enum Removal<'a> {
+    Link { parent: &'a FSNode<'a>, path: MPPath<'a>, node: &'a FSNode<'a> },
+    Skip { parent: &'a FSNode<'a>, path: MPPath<'a>, node: &'a FSNode<'a> },
+}
+
 pub struct FactSet<'a> {
     pub root: Box<FSNode<'a>>,
+    nodes: Arena<FSNode<'a>>,
 }
 
 
@@ -55,6 +62,7 @@ impl<'a> FactSet<'a> {
     pub fn new () -> FactSet<'a> {
         FactSet {
             root: Box::new(FSNode::new(1)),
+            nodes: Arena::new(),
          }
     }
     pub fn add_fact (&'a self, fact: Vec<MPPath<'a>>) {
@@ -68,10 +76,86 @@ impl<'a> FactSet<'a> {
         let (response, unique) = self.root.query_paths(paths, matching, response);
         (response, fact, unique)
     }
+    /// Like `ask_fact`, but extending the given matching,
+    /// so variables already bound in it must match their values.
+    pub fn ask_fact_matching (&'a self, fact: Vec<MPPath<'a>>, matching: MPMatching<'a>) -> (Vec<MPMatching<'a>>, Vec<MPPath<'a>>) {
+        let response: Vec<MPMatching> = vec![];
+        let paths: &[MPPath] = unsafe { mem::transmute( fact.as_slice() ) };
+        let (response, _) = self.root.query_paths(paths, matching, response);
+        (response, fact)
+    }
     pub fn ask_fact_bool (&'a self, fact: Vec<MPPath<'a>>) -> (bool, Vec<MPPath<'a>>) {
         let (resp, fact, _) = self.ask_fact(fact);
         (resp.len() > 0, fact)
     }
+    /// Remove a fact, pruning the nodes that are left without children.
+    /// Returns whether the fact was found.
+    pub fn remove_fact (&'a self, paths: Vec<MPPath<'a>>) -> bool {
+        let mut parent: &FSNode = &self.root;
+        let mut steps: Vec<Removal> = vec![];
+        for path in paths {
+            if path.value.is_empty {
+                continue;
+            }
+            if path.value.in_var_range {
+                let (opt_child, path) = parent.get_lchild(path);
+                let child = match opt_child {
+                    Some(child) => child,
+                    None => return false,
+                };
+                if !path.value.is_leaf {
+                    steps.push(Removal::Skip { parent, path, node: child });
+                    continue;
+                }
+                steps.push(Removal::Link { parent, path, node: child });
+                parent = child;
+            } else {
+                let (opt_child, path) = parent.get_child(path);
+                let child = match opt_child {
+                    Some(child) => child,
+                    None => return false,
+                };
+                steps.push(Removal::Link { parent, path, node: child });
+                parent = child;
+            }
+        }
+        for (i, step) in steps.iter().enumerate().rev() {
+            match step {
+                Removal::Skip { parent, path, node } => {
+                    if node.is_childless() {
+                        parent.lchildren.borrow_mut().remove(path);
+                    }
+                },
+                Removal::Link { parent, path, node } => {
+                    if !node.is_childless() {
+                        continue;
+                    }
+                    parent.unlink(path, node);
+                    // nodes for var ranges may also point to the nodes that follow them
+                    for previous in steps[..i].iter() {
+                        if let Removal::Skip { node: skip, .. } = previous {
+                            skip.unlink(path, node);
+                        }
+                    }
+                },
+            }
+        }
+        true
+    }
+    /// The number of nodes linked in the tree, besides the root.
+    /// Their memory is only freed along with the fact set.
+    pub fn node_count(&self) -> usize {
+        let mut linked: HashSet<*const FSNode<'a>> = HashSet::new();
+        let mut pending: Vec<&FSNode<'a>> = vec![&self.root];
+        while let Some(node) = pending.pop() {
+            for child in node.children.borrow().values().chain(node.lchildren.borrow().values()) {
+                if linked.insert(*child) {
+                    pending.push(child);
+                }
+            }
+        }
+        linked.len()
+    }
     pub fn follow_and_create_paths(&'a self,
                                    mut parent: &'a FSNode<'a>,
                                    mut paths: Vec<MPPath<'a>>,
@@ -171,7 +255,7 @@ impl<'a> FactSet<'a> {
                         index: usize,
                     ) -> (&'a FSNode<'a>, CarryOver<'a>) {
 
-        let child_ref = Box::leak(Box::new(child));
+        let child_ref = self.nodes.alloc(child);
         let (new_carry, more) = carry.node(index);
         carry = new_carry;
         if more.is_some() {
@@ -187,7 +271,7 @@ impl<'a> FactSet<'a> {
                          mut carry: CarryOver<'a>,
                          index: usize,
                         ) -> (&'a FSNode<'a>, CarryOver<'a>) {
-        let child_ref = Box::leak(Box::new(child));
+        let child_ref = self.nodes.alloc(child);
         let (new_carry, more) = carry.node(index);
         carry = new_carry;
         if more.is_some() {
@@ -210,6 +294,17 @@ impl<'a> FSNode<'a> {
             lchildren: RefCell::new(HashMap::with_capacity(capacity)),
         }
     }
+    fn is_childless(&self) -> bool {
+        self.children.borrow().is_empty() && self.lchildren.borrow().is_empty()
+    }
+    fn unlink(&self, path: &MPPath<'a>, node: &FSNode<'a>) {
+        for map in [&self.children, &self.lchildren].iter() {
+            let mut map = map.borrow_mut();
+            if map.get(path).is_some_and(|child| std::ptr::eq(*child, node)) {
+                map.remove(path);
+            }
+        }
+    }
     pub fn get_child(&'a self, path: MPPath<'a>) -> (Option<&'a Self>, MPPath<'a>) {
         let children = self.children.borrow();
         match children.get(&path) {
@@ -0,0 +1,388 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use pest::{ Atomicity, MatchDir, ParseResult, ParserState, RuleType };
+use pest::error::Error as PestError;
+use pest::iterators::Pairs;
+use pest::unicode;
+use pest_meta::ast::RuleType as MetaRuleType;
+use pest_meta::optimizer::{ self, OptimizedExpr, OptimizedRule };
+use pest_meta::parser::{ self, Rule as MetaRule };
+use pest_meta::validator;
+
+use crate::constants;
+use crate::error::{ self, MPError };
+
+
+/// The grammar of the facts held in a `KnowledgeBase`.
+///
+/// It is implemented by `#[derive(KBGen)]` for the pest parser of the given grammar,
+/// and by `Grammar`, for grammars loaded at runtime.
+pub trait FactGrammar {
+    /// The rules of the grammar, as they appear in the parse trees.
+    /// Their `Debug` representation must be the name of the rule in the grammar.
+    type Rule<'g>: RuleType where Self: 'g;
+
+    /// Parse `text` with the `fact` production of the grammar.
+    fn parse_fact<'g, 'i>(&'g self, text: &'i str) -> Result<Pairs<'i, Self::Rule<'g>>, Box<PestError<Self::Rule<'g>>>>;
+
+    /// The text of the grammar, that identifies the knowledge bases built with it.
+    fn source(&self) -> &str;
+}
+
+
+/// The name of a rule in a grammar loaded at runtime.
+///
+/// It is printed with `{:?}` as the bare name of the rule,
+/// like the variants of the `Rule` enums that pest derives,
+/// so that parse trees from both kinds of grammars give the same segments.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GrammarRule<'g>(&'g str);
+
+impl<'g> GrammarRule<'g> {
+    pub fn name(&self) -> &'g str {
+        self.0
+    }
+}
+
+impl<'g> fmt::Debug for GrammarRule<'g> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+type State<'i, 'g> = Box<ParserState<'i, GrammarRule<'g>>>;
+
+
+/// A fact grammar in the pest syntax, loaded at runtime
+/// and interpreted rather than compiled.
+///
+/// Like the grammars given to `#[derive(KBGen)]`,
+/// it must have a `fact` rule and a `var` rule.
+pub struct Grammar {
+    source: String,
+    rules: HashMap<String, OptimizedRule>,
+    /// The tests of the Unicode property builtins used in the grammar.
+    properties: HashMap<String, Box<dyn Fn(char) -> bool>>,
+}
+
+impl Grammar {
+    pub fn new(text: &str) -> Result<Grammar, MPError> {
+        let pairs = match parser::parse(MetaRule::grammar_rules, text) {
+            Ok(pairs) => pairs,
+            Err(err) => return Err(MPError::grammar(text, err)),
+        };
+        if let Err(mut errors) = validator::validate_pairs(pairs.clone()) {
+            return Err(MPError::grammar(text, errors.remove(0)));
+        }
+        let ast = match parser::consume_rules(pairs) {
+            Ok(ast) => ast,
+            Err(mut errors) => return Err(MPError::grammar(text, errors.remove(0))),
+        };
+        let rules: HashMap<String, OptimizedRule> = optimizer::optimize(ast).into_iter()
+            .map(|rule| (rule.name.clone(), rule))
+            .collect();
+        for required in &["fact", constants::VAR_RULE_NAME] {
+            if !rules.contains_key(*required) {
+                return Err(MPError::Grammar {
+                    text: text.to_string(),
+                    line: 1,
+                    col: 1,
+                    message: format!("the grammar has no `{}` rule", required),
+                });
+            }
+        }
+        let mut properties: HashMap<String, Box<dyn Fn(char) -> bool>> = HashMap::new();
+        for rule in rules.values() {
+            let mut names = vec![];
+            idents(&rule.expr, &mut names);
+            for name in names {
+                if rules.contains_key(name) || is_builtin(name) || properties.contains_key(name) {
+                    continue;
+                }
+                match unicode::by_name(name) {
+                    Some(test) => {
+                        properties.insert(name.to_string(), test);
+                    },
+                    None => {
+                        let (line, col) = error::line_col(text, ident_offset(text, name));
+                        return Err(MPError::Grammar {
+                            text: text.to_string(),
+                            line,
+                            col,
+                            message: format!("the builtin rule `{}` is not supported", name),
+                        });
+                    },
+                }
+            }
+        }
+        Ok(Grammar { source: text.to_string(), rules, properties })
+    }
+
+    /// Parse `input` starting with the rule named `rule`.
+    pub fn parse<'g, 'i>(&'g self, rule: &'g str, input: &'i str) -> Result<Pairs<'i, GrammarRule<'g>>, Box<PestError<GrammarRule<'g>>>> {
+        pest::state(input, |state| self.parse_rule(rule, state)).map_err(Box::new)
+    }
+
+    fn parse_rule<'g, 'i>(&'g self, name: &'g str, state: State<'i, 'g>) -> ParseResult<State<'i, 'g>> {
+        match name {
+            "ANY" => return state.skip(1),
+            "EOI" => return state.rule(GrammarRule("EOI"), |state| state.end_of_input()),
+            "SOI" => return state.start_of_input(),
+            "PEEK" => return state.stack_peek(),
+            "PEEK_ALL" => return state.stack_match_peek(),
+            "POP" => return state.stack_pop(),
+            "POP_ALL" => return state.stack_match_pop(),
+            "DROP" => return state.stack_drop(),
+            "NEWLINE" => {
+                return state.match_string("\n")
+                    .or_else(|state| state.match_string("\r\n"))
+                    .or_else(|state| state.match_string("\r"));
+            },
+            _ => {},
+        }
+        if let Some(test) = builtin_class(name) {
+            return state.match_char_by(test);
+        }
+        if let Some(test) = self.properties.get(name) {
+            return state.match_char_by(test);
+        }
+        let rule = match self.rules.get(name) {
+            Some(rule) => rule,
+            // undefined rules are rejected by the validator, and unsupported builtins by `new`
+            None => return Err(state),
+        };
+        let rule_name = GrammarRule(&rule.name);
+        let expr = &rule.expr;
+        if name == "WHITESPACE" || name == "COMMENT" {
+            return match rule.ty {
+                MetaRuleType::Silent => state.atomic(Atomicity::Atomic, |state| self.parse_expr(expr, state)),
+                _ => state.rule(rule_name, |state| {
+                    state.atomic(Atomicity::Atomic, |state| self.parse_expr(expr, state))
+                }),
+            };
+        }
+        match rule.ty {
+            MetaRuleType::Normal => state.rule(rule_name, |state| self.parse_expr(expr, state)),
+            MetaRuleType::Silent => self.parse_expr(expr, state),
+            MetaRuleType::Atomic => state.rule(rule_name, |state| {
+                state.atomic(Atomicity::Atomic, |state| self.parse_expr(expr, state))
+            }),
+            MetaRuleType::CompoundAtomic => state.atomic(Atomicity::CompoundAtomic, |state| {
+                state.rule(rule_name, |state| self.parse_expr(expr, state))
+            }),
+            MetaRuleType::NonAtomic => state.atomic(Atomicity::NonAtomic, |state| {
+                state.rule(rule_name, |state| self.parse_expr(expr, state))
+            }),
+        }
+    }
+
+    fn parse_expr<'g, 'i>(&'g self, expr: &'g OptimizedExpr, state: State<'i, 'g>) -> ParseResult<State<'i, 'g>> {
+        match expr {
+            OptimizedExpr::Str(string) => state.match_string(string),
+            OptimizedExpr::Insens(string) => state.match_insensitive(string),
+            OptimizedExpr::Range(start, end) => {
+                let start = start.chars().next().expect("range start");
+                let end = end.chars().next().expect("range end");
+                state.match_range(start..end)
+            },
+            OptimizedExpr::Ident(name) => self.parse_rule(name, state),
+            OptimizedExpr::PeekSlice(start, end) => state.stack_match_peek_slice(*start, *end, MatchDir::BottomToTop),
+            OptimizedExpr::PosPred(expr) => state.lookahead(true, |state| self.parse_expr(expr, state)),
+            OptimizedExpr::NegPred(expr) => state.lookahead(false, |state| self.parse_expr(expr, state)),
+            OptimizedExpr::Seq(lhs, rhs) => state.sequence(|state| {
+                self.parse_expr(lhs, state)
+                    .and_then(|state| self.skip(state))
+                    .and_then(|state| self.parse_expr(rhs, state))
+            }),
+            OptimizedExpr::Choice(lhs, rhs) => {
+                self.parse_expr(lhs, state)
+                    .or_else(|state| self.parse_expr(rhs, state))
+            },
+            OptimizedExpr::Opt(expr) => state.optional(|state| self.parse_expr(expr, state)),
+            OptimizedExpr::Rep(expr) => state.sequence(|state| {
+                state.optional(|state| {
+                    self.parse_expr(expr, state).and_then(|state| {
+                        state.repeat(|state| {
+                            state.sequence(|state| {
+                                self.skip(state).and_then(|state| self.parse_expr(expr, state))
+                            })
+                        })
+                    })
+                })
+            }),
+            OptimizedExpr::Push(expr) => state.stack_push(|state| self.parse_expr(expr, state)),
+            OptimizedExpr::Skip(strings) => {
+                let strings: Vec<&str> = strings.iter().map(|s| s.as_str()).collect();
+                state.skip_until(&strings)
+            },
+            OptimizedExpr::RestoreOnErr(expr) => state.restore_on_err(|state| self.parse_expr(expr, state)),
+        }
+    }
+
+    /// Implicit whitespace and comments between the terms of sequences and repetitions.
+    fn skip<'g, 'i>(&'g self, state: State<'i, 'g>) -> ParseResult<State<'i, 'g>> {
+        if state.atomicity() != Atomicity::NonAtomic {
+            return Ok(state);
+        }
+        let whitespace = self.rules.contains_key("WHITESPACE");
+        let comment = self.rules.contains_key("COMMENT");
+        match (whitespace, comment) {
+            (false, false) => Ok(state),
+            (true, false) => state.repeat(|state| self.parse_rule("WHITESPACE", state)),
+            (false, true) => state.repeat(|state| self.parse_rule("COMMENT", state)),
+            (true, true) => state.sequence(|state| {
+                state.repeat(|state| self.parse_rule("WHITESPACE", state)).and_then(|state| {
+                    state.repeat(|state| {
+                        state.sequence(|state| {
+                            self.parse_rule("COMMENT", state).and_then(|state| {
+                                state.repeat(|state| self.parse_rule("WHITESPACE", state))
+                            })
+                        })
+                    })
+                })
+            }),
+        }
+    }
+}
+
+impl FactGrammar for Grammar {
+    type Rule<'g> = GrammarRule<'g>;
+
+    fn parse_fact<'g, 'i>(&'g self, text: &'i str) -> Result<Pairs<'i, GrammarRule<'g>>, Box<PestError<GrammarRule<'g>>>> {
+        self.parse("fact", text)
+    }
+
+    fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+/// The builtin rules of pest other than the Unicode properties.
+fn is_builtin(name: &str) -> bool {
+    matches!(name, "ANY" | "EOI" | "SOI" | "PEEK" | "PEEK_ALL" | "POP" | "POP_ALL" | "DROP" | "NEWLINE")
+        || builtin_class(name).is_some()
+}
+
+/// The ASCII builtin rules of pest, that match a single character.
+/// The Unicode property builtins are looked up in `pest::unicode`.
+fn builtin_class(name: &str) -> Option<fn(char) -> bool> {
+    let test: fn(char) -> bool = match name {
+        "ASCII_DIGIT" => |c| c.is_ascii_digit(),
+        "ASCII_NONZERO_DIGIT" => |c| ('1'..='9').contains(&c),
+        "ASCII_BIN_DIGIT" => |c| c == '0' || c == '1',
+        "ASCII_OCT_DIGIT" => |c| ('0'..='7').contains(&c),
+        "ASCII_HEX_DIGIT" => |c| c.is_ascii_hexdigit(),
+        "ASCII_ALPHA_LOWER" => |c| c.is_ascii_lowercase(),
+        "ASCII_ALPHA_UPPER" => |c| c.is_ascii_uppercase(),
+        "ASCII_ALPHA" => |c| c.is_ascii_alphabetic(),
+        "ASCII_ALPHANUMERIC" => |c| c.is_ascii_alphanumeric(),
+        "ASCII" => |c| c.is_ascii(),
+        _ => return None,
+    };
+    Some(test)
+}
+
+/// The names of the rules used in `expr`.
+fn idents<'e>(expr: &'e OptimizedExpr, names: &mut Vec<&'e str>) {
+    match expr {
+        OptimizedExpr::Ident(name) => names.push(name),
+        OptimizedExpr::Seq(lhs, rhs) | OptimizedExpr::Choice(lhs, rhs) => {
+            idents(lhs, names);
+            idents(rhs, names);
+        },
+        OptimizedExpr::PosPred(expr)
+        | OptimizedExpr::NegPred(expr)
+        | OptimizedExpr::Opt(expr)
+        | OptimizedExpr::Rep(expr)
+        | OptimizedExpr::Push(expr)
+        | OptimizedExpr::RestoreOnErr(expr) => idents(expr, names),
+        _ => {},
+    }
+}
+
+/// The byte offset of the first use of the rule `name` in the grammar `text`.
+fn ident_offset(text: &str, name: &str) -> usize {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(name)
+        .map(|(offset, _)| offset)
+        .find(|offset| {
+            !text[..*offset].ends_with(is_ident) && !text[offset + name.len()..].starts_with(is_ident)
+        })
+        .unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+        var         = @{ ("<" ~ &ASCII_ALPHA_UPPER) ~ ASCII_ALPHA_UPPER ~ ASCII_ALPHA* ~ ('0'..'9')* ~ ">" }
+        fact        = _{ SOI ~ rfact ~ EOI }
+        rfact       = { var_range ~ pred ~ var_range }
+        pred        = @{ "∈" | "⊆" }
+        v_name      = @{ ASCII_ALPHANUMERIC+ }
+        var_range   = _{ v_name | var }
+        WHITESPACE  = { (" " | "\t" | NEWLINE)+ }
+    "#;
+
+    #[test]
+    fn test_parse_with_runtime_grammar() {
+        let grammar = Grammar::new(GRAMMAR).unwrap();
+        let pairs = grammar.parse("fact", "susan ∈ <X1>").unwrap();
+        let names: Vec<String> = pairs.flatten()
+            .map(|pair| format!("{:?} {}", pair.as_rule(), pair.as_str()))
+            .collect();
+        assert_eq!(names, vec![
+            "rfact susan ∈ <X1>",
+            "v_name susan",
+            "WHITESPACE  ",
+            "pred ∈",
+            "WHITESPACE  ",
+            "var <X1>",
+            "EOI ",
+        ]);
+        assert!(grammar.parse("fact", "susan ∈").is_err());
+    }
+
+    #[test]
+    fn test_reject_bad_grammars() {
+        match Grammar::new("fact = { \"a\" ") {
+            Err(MPError::Grammar { .. }) => {},
+            _ => panic!("a broken grammar was accepted"),
+        }
+        match Grammar::new("fact = { \"a\" }") {
+            Err(MPError::Grammar { message, .. }) => assert_eq!(message, "the grammar has no `var` rule"),
+            _ => panic!("a grammar without vars was accepted"),
+        }
+    }
+
+    #[test]
+    fn test_unicode_builtins() {
+        let grammar = Grammar::new(&GRAMMAR.replace("ASCII_ALPHANUMERIC+", "(CASED_LETTER | MATH)+")).unwrap();
+        for fact in &["σωκράτης ∈ x+y", "∀x ∈ Ω"] {
+            assert!(grammar.parse("fact", fact).is_ok(), "{}", fact);
+        }
+        assert!(grammar.parse("fact", "x1 ∈ y").is_err());
+    }
+}
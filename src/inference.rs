@@ -0,0 +1,105 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! Limits on the inference run by `KnowledgeBase::tell_with`, how it ended,
+//! and the steps run by `KnowledgeBase::step`.
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::Instant;
+
+
+/// A flag to stop inference from elsewhere, e.g. from another thread,
+/// or from a signal handler. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits on inference; with the default options there are none.
+#[derive(Debug, Clone, Default)]
+pub struct TellOptions {
+    /// The maximum number of activations to process.
+    pub max_activations: Option<usize>,
+    /// The time by which inference must stop.
+    pub deadline: Option<Instant>,
+    pub cancel: Option<CancelToken>,
+}
+
+/// How inference ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Nothing is left to be inferred.
+    Fixpoint,
+    /// `max_activations` activations were processed.
+    BudgetExhausted,
+    DeadlineReached,
+    Cancelled,
+}
+
+/// Keeps account of a run of inference against its limits.
+pub struct Budget<'o> {
+    options: &'o TellOptions,
+    used: usize,
+}
+
+impl<'o> Budget<'o> {
+    pub fn new(options: &'o TellOptions) -> Budget<'o> {
+        Budget { options, used: 0 }
+    }
+    /// Whether inference has to stop before processing another activation,
+    /// and why. Otherwise, the activation is counted.
+    pub fn stop(&mut self) -> Option<Status> {
+        if self.options.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled()) {
+            return Some(Status::Cancelled);
+        }
+        if self.options.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(Status::DeadlineReached);
+        }
+        if self.options.max_activations.is_some_and(|max| self.used >= max) {
+            return Some(Status::BudgetExhausted);
+        }
+        self.used += 1;
+        None
+    }
+    /// The number of activations counted.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+/// An activation processed by `KnowledgeBase::step`, described as it was
+/// before being processed, and the activations it added to the queues.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub activation: String,
+    pub enqueued: Vec<String>,
+}
@@ -0,0 +1,250 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! Write-ahead journals of the knowledge told to knowledge bases.
+//!
+//! A journal starts with a header line naming the snapshot it continues,
+//! by its checksum, or `-` if it starts from an empty knowledge base.
+//! Each record is a line with its kind (`tell`, `retract`, or `remove`, `enable`
+//! or `disable` for rules, `resume` for resumed inference or a step, with no text,
+//! or `stopped` after inference stopped before reaching a fixpoint,
+//! with the number of activations processed)
+//! and the length in bytes of its text, followed by the text and a newline.
+
+use std::fs::{ self, File, OpenOptions };
+use std::hash::Hasher;
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+
+use crate::snapshot::SnapshotError;
+use crate::ruletree::RuleId;
+use crate::tms::Fnv;
+
+
+const HEADER: &str = "modus_ponens journal 1";
+
+/// A record in a journal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Tell(String),
+    Retract(String),
+    RemoveRule(RuleId),
+    EnableRule(RuleId),
+    DisableRule(RuleId),
+    Resume,
+    /// Inference, for the previous record, stopped after processing the given number of activations.
+    Stopped(usize),
+}
+
+/// An open journal, to which the knowledge told to a knowledge base is appended.
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    snapshot: Option<PathBuf>,
+}
+
+impl Journal {
+    /// Open the journal at `path`, calling `replay` with each of its records,
+    /// if it continues from the snapshot with checksum `base`
+    /// (`None` if no snapshot was loaded).
+    /// `Stopped` records are not replayed on their own, but given to `replay`
+    /// along with the record before them, as the number of activations to process for it.
+    /// A record with no `Stopped` record after it, because inference reached a fixpoint,
+    /// or because of a crash before the stop was recorded, is replayed with no limit.
+    ///
+    /// If the journal does not exist, it is created.
+    /// If it continues from an older snapshot, its records are already
+    /// in the loaded snapshot (compaction was interrupted), so it is started again.
+    /// A journal that starts from an empty knowledge base is only started again
+    /// if the journal written by that compaction is there to replace it;
+    /// otherwise it is an error, since its records would be lost.
+    /// A last record cut short by a crash is dropped.
+    pub fn open<F>(path: &Path, snapshot: Option<&Path>, base: Option<u64>, mut replay: F) -> Result<Journal, SnapshotError>
+        where F: FnMut(Entry, Option<usize>) -> Result<(), SnapshotError>
+    {
+        let snapshot = snapshot.map(|snapshot| snapshot.to_path_buf());
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Journal::create(path, snapshot, base);
+            },
+            Err(err) => return Err(err.into()),
+        };
+        let (journal_base, entries, end) = parse(&data)?;
+        if journal_base != base {
+            return match (journal_base, base) {
+                (_, None) => Err(SnapshotError::Journal(String::from("it continues a snapshot that was not loaded"))),
+                (None, Some(_)) if !Journal::compacted(path, base) => {
+                    Err(SnapshotError::Journal(String::from("it starts from an empty knowledge base, but a snapshot was loaded")))
+                },
+                _ => Journal::create(path, snapshot, base),
+            };
+        }
+        let mut entries = entries.into_iter().peekable();
+        while let Some(entry) = entries.next() {
+            if let Entry::Stopped(_) = entry {
+                return Err(SnapshotError::Journal(String::from("a stop record with no inference before it")));
+            }
+            let stopped = match entries.peek() {
+                Some(Entry::Stopped(activations)) => Some(*activations),
+                _ => None,
+            };
+            if stopped.is_some() {
+                entries.next();
+            }
+            replay(entry, stopped)?;
+        }
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(end as u64)?;
+        drop(file);
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Journal { file, path: path.to_path_buf(), snapshot })
+    }
+
+    /// Append a record, making sure it is on disk before returning.
+    pub fn append(&mut self, entry: &Entry) -> io::Result<()> {
+        let (kind, text) = match entry {
+            Entry::Tell(text) => ("tell", text.clone()),
+            Entry::Retract(text) => ("retract", text.clone()),
+            Entry::RemoveRule(id) => ("remove", id.to_string()),
+            Entry::EnableRule(id) => ("enable", id.to_string()),
+            Entry::DisableRule(id) => ("disable", id.to_string()),
+            Entry::Resume => ("resume", String::new()),
+            Entry::Stopped(activations) => ("stopped", activations.to_string()),
+        };
+        let record = format!("{} {}\n{}\n", kind, text.len(), text);
+        self.file.write_all(record.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    /// Replace the snapshot with the given one, and start the journal again from it.
+    pub fn compact(&mut self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        let snapshot_path = match &self.snapshot {
+            Some(snapshot_path) => snapshot_path.clone(),
+            None => return Err(SnapshotError::Journal(String::from("there is no snapshot file to compact into"))),
+        };
+        let tmp = with_suffix(&snapshot_path, ".tmp");
+        write_synced(&tmp, snapshot)?;
+        // once the new snapshot is in place, the old journal is recognised as stale,
+        // so a crash before the new journal is in place loses nothing;
+        // the new journal is written first, for a stale journal with no snapshot before it
+        // to be told from one that was given the wrong snapshot
+        let base = Some(checksum(snapshot));
+        let journal_tmp = Journal::write_header(&self.path, base)?;
+        fs::rename(&tmp, &snapshot_path)?;
+        fs::rename(&journal_tmp, &self.path)?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.file = file;
+        self.snapshot = Some(snapshot_path);
+        Ok(())
+    }
+
+    fn create(path: &Path, snapshot: Option<PathBuf>, base: Option<u64>) -> Result<Journal, SnapshotError> {
+        let tmp = Journal::write_header(path, base)?;
+        fs::rename(&tmp, path)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Journal { file, path: path.to_path_buf(), snapshot })
+    }
+
+    /// Write a journal with no records, to be moved to `path`, and return where it was written.
+    fn write_header(path: &Path, base: Option<u64>) -> io::Result<PathBuf> {
+        let base = match base {
+            Some(base) => format!("{:016x}", base),
+            None => String::from("-"),
+        };
+        let tmp = with_suffix(path, ".tmp");
+        write_synced(&tmp, format!("{} {}\n", HEADER, base).as_bytes())?;
+        Ok(tmp)
+    }
+
+    /// Whether a compaction into the snapshot with checksum `base` was interrupted
+    /// after replacing the snapshot, but before replacing the journal at `path`.
+    fn compacted(path: &Path, base: Option<u64>) -> bool {
+        match fs::read(with_suffix(path, ".tmp")) {
+            Ok(data) => matches!(parse(&data), Ok((tmp_base, entries, _)) if tmp_base == base && entries.is_empty()),
+            Err(_) => false,
+        }
+    }
+}
+
+/// FNV-1a, to identify snapshots independently of the build.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// The base, the complete records, and the length of the data up to the end of them.
+fn parse(data: &[u8]) -> Result<(Option<u64>, Vec<Entry>, usize), SnapshotError> {
+    let bad = |message: &str| SnapshotError::Journal(String::from(message));
+    let (header, mut pos) = match line(data, 0) {
+        Some(found) => found,
+        None => return Err(bad("no header")),
+    };
+    let base = match header.strip_prefix(HEADER) {
+        Some(" -") => None,
+        Some(base) => Some(u64::from_str_radix(base.trim(), 16).map_err(|_| bad("bad header"))?),
+        None => return Err(bad("bad header")),
+    };
+    let mut entries = vec![];
+    while let Some((record, after)) = line(data, pos) {
+        let mut parts = record.splitn(2, ' ');
+        let kind = parts.next().unwrap_or("");
+        let len: usize = parts.next().and_then(|len| len.parse().ok()).ok_or_else(|| bad("bad record"))?;
+        let end = after + len;
+        if data.len() < end + 1 {
+            break;
+        }
+        if data[end] != b'\n' {
+            return Err(bad("bad record"));
+        }
+        let text = String::from_utf8(data[after..end].to_vec()).map_err(|_| bad("bad record"))?;
+        entries.push(match kind {
+            "tell" => Entry::Tell(text),
+            "retract" => Entry::Retract(text),
+            "remove" => Entry::RemoveRule(text.parse().map_err(|_| bad("bad rule id"))?),
+            "enable" => Entry::EnableRule(text.parse().map_err(|_| bad("bad rule id"))?),
+            "disable" => Entry::DisableRule(text.parse().map_err(|_| bad("bad rule id"))?),
+            "resume" => Entry::Resume,
+            "stopped" => Entry::Stopped(text.parse().map_err(|_| bad("bad number of activations"))?),
+            _ => return Err(bad("bad record")),
+        });
+        pos = end + 1;
+    }
+    Ok((base, entries, pos))
+}
+
+/// The complete line starting at `start`, and the position after it.
+fn line(data: &[u8], start: usize) -> Option<(&str, usize)> {
+    let len = data[start..].iter().position(|byte| *byte == b'\n')?;
+    let text = std::str::from_utf8(&data[start..start + len]).ok()?;
+    Some((text, start + len + 1))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
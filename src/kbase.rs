@@ -17,14 +17,22 @@
 // along with any part of the modus_ponens project.    
 // If not, see <http://www.gnu.org/licenses/>.
 
-use crate::matching::MPMatching;
+use crate::error::{ MPError, Diagnostic };
+use crate::bindings::Bindings;
 
-pub trait KBase<'a> {
-    fn tell(&'a self, knowledge: &'a str);
-    fn ask(&'a self, knowledge: &'a str) -> Vec<MPMatching<'a>>;
+pub trait KBase {
+    fn tell(&self, knowledge: &str) -> Result<(), MPError>;
+    fn ask(&self, knowledge: &str) -> Result<Vec<Bindings>, MPError>;
+    /// Remove facts, together with every fact and rule that was derived from them
+    /// and cannot be derived from what remains in the knowledge base.
+    fn retract(&self, knowledge: &str) -> Result<(), MPError>;
+    /// Rule matches skipped so far because their transforms or conditions failed.
+    fn diagnostics(&self) -> Vec<Diagnostic>;
+    /// Like `diagnostics`, but also empties the list kept in the knowledge base.
+    fn take_diagnostics(&self) -> Vec<Diagnostic>;
 }
 
-pub trait KBGen<'a> {
-    type Output: KBase<'a>;
+pub trait KBGen {
+    type Output: KBase;
     fn gen_kb() -> Self::Output;
 }
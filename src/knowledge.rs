@@ -17,337 +17,2429 @@
 // along with any part of the modus_ponens project.    
 // If not, see <http://www.gnu.org/licenses/>.
 
-extern crate proc_macro;
-extern crate proc_macro2;
-extern crate syn;
+use std::collections::{ BTreeMap, HashMap, HashSet, VecDeque };
+use std::cell::{ Cell, Ref, RefCell };
+use std::fs;
+use std::io::{ BufRead, BufReader, Read, Write };
+use std::mem;
+use std::rc::Rc;
+use std::path::{ Path, PathBuf };
 
-use proc_macro2::TokenStream;
+use log::{ info, trace, warn };
 
+use crate::constants;
+use crate::activation::{ ParseResult, Activation };
+use crate::agenda::{ Agenda, Strategy };
+use crate::inference::{ Budget, Status, Step, TellOptions };
+use crate::reader::{ BulkLoad, Sentence, Sentences };
+use crate::facttree::FactSet;
+use crate::kbase::KBase;
+use crate::lexicon::Lexicon;
+use crate::matching::{ MPMatching, get_real_matching };
+use crate::path::MPPath;
+use crate::ruletree::{ Antecedents, MPRule, Response, RuleSet, RuleRef, RuleId, RuleInfo, RuleMeta, PartialRule };
+use crate::parser::MPParser;
+use crate::transform::TParser;
+use crate::condition::CParser;
+use crate::error::{ MPError, Diagnostic, line_col };
+use crate::bindings::Bindings;
+use crate::grammar::FactGrammar;
+use crate::snapshot::{ Encoder, Decoder, SnapshotError };
+use crate::journal::{ self, Journal, Entry };
+use crate::proof::Proof;
+use crate::output::{ Output, OutputHandler, OutputTarget, HandlerId, normalize_rule };
+use crate::tms::{ self, FactKey, Justifications };
 
-pub fn derive_kb() -> TokenStream {
-    quote! {
 
-        pub struct Queues<'a> {
-            rule_queue: VecDeque<Activation<'a>>,
-            match_queue: VecDeque<Activation<'a>>,
-            fact_queue: VecDeque<Activation<'a>>,
+/// The activations waiting to be processed.
+/// Rules are added first, then the rules they match are matched,
+/// then facts are added, and only then the agenda fires the next rule.
+pub struct Queues<'a> {
+    rule_queue: VecDeque<Activation<'a>>,
+    match_queue: VecDeque<Activation<'a>>,
+    fact_queue: VecDeque<Activation<'a>>,
+    agenda: Agenda<'a>,
+}
+impl<'a> Queues<'a> {
+
+    pub fn new () -> Queues<'a> {
+        Self {
+            rule_queue: VecDeque::new(),
+            match_queue: VecDeque::new(),
+            fact_queue: VecDeque::new(),
+            agenda: Agenda::new(),
         }
-        impl<'a> Queues<'a> {
+    }
+    pub fn len(&self) -> usize {
+        self.rule_queue.len() + self.match_queue.len() + self.fact_queue.len() + self.agenda.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rule_queue.is_empty() && self.match_queue.is_empty() && self.fact_queue.is_empty() && self.agenda.is_empty()
+    }
+    pub fn rule_queue(&self) -> &VecDeque<Activation<'a>> {
+        &self.rule_queue
+    }
+    pub fn match_queue(&self) -> &VecDeque<Activation<'a>> {
+        &self.match_queue
+    }
+    pub fn fact_queue(&self) -> &VecDeque<Activation<'a>> {
+        &self.fact_queue
+    }
+    pub fn agenda(&self) -> &Agenda<'a> {
+        &self.agenda
+    }
+    /// Drop the activations of the rule with the given id, and return how many there were.
+    fn purge_rule(&mut self, id: RuleId) -> usize {
+        let len = self.len();
+        let keep = |activation: &Activation<'a>| activation.rule_id() != Some(id);
+        self.rule_queue.retain(keep);
+        self.match_queue.retain(keep);
+        self.agenda.retain(keep);
+        len - self.len()
+    }
+    /// The activations waiting, in the order in which they will be processed,
+    /// unless processing them adds activations ahead of the rest.
+    pub fn activations(&self) -> Vec<&Activation<'a>> {
+        let mut activations: Vec<&Activation<'a>> = self.rule_queue.iter()
+            .chain(self.match_queue.iter())
+            .chain(self.fact_queue.iter())
+            .collect();
+        activations.extend(self.agenda.activations());
+        activations
+    }
+    fn pop_next(&mut self) -> Option<Activation<'a>> {
+        self.rule_queue.pop_front()
+            .or_else(|| self.match_queue.pop_front())
+            .or_else(|| self.fact_queue.pop_front())
+            // rules only fire once everything they might compete with is on the agenda
+            .or_else(|| self.agenda.pop())
+    }
+}
+impl<'a> Default for Queues<'a> {
+    fn default() -> Self {
+        Queues::new()
+    }
+}
 
-            pub fn new () -> Queues<'a> {
-                Self {
-                    rule_queue: VecDeque::new(),
-                    match_queue: VecDeque::new(),
-                    fact_queue: VecDeque::new(),
-                }
-            }
+/// A callback for new facts that match a pattern, see `KnowledgeBase::subscribe`.
+pub type Subscriber = Box<dyn FnMut(&str, &Bindings)>;
+
+/// A knowledge base for facts that follow the grammar `P`.
+/// It owns all the facts and rules it is told,
+/// which are freed when it is dropped.
+pub struct KnowledgeBase<P: FactGrammar + 'static> {
+    engine: Box<Engine<'static, P>>,
+}
+impl<P: FactGrammar + 'static> KnowledgeBase<P> {
+    pub fn new(grammar: P) -> KnowledgeBase<P> {
+        KnowledgeBase {
+            engine: Box::new(Engine::new(grammar)),
+        }
+    }
+    /// Write a snapshot of the knowledge base,
+    /// from which `load` can rebuild it without running inference again.
+    /// The diagnostics are not kept in the snapshot.
+    /// If inference was stopped, the activations left are kept in the snapshot,
+    /// to be processed by `resume` once loaded.
+    pub fn save<W: Write>(&self, out: W) -> Result<(), SnapshotError> {
+        self.engine().save(out)
+    }
+    /// Rebuild a knowledge base from a snapshot written by `save`,
+    /// which must have been taken from a knowledge base with the same grammar.
+    pub fn load<R: Read>(grammar: P, input: R) -> Result<KnowledgeBase<P>, SnapshotError> {
+        let kb = KnowledgeBase::new(grammar);
+        kb.engine().restore(BufReader::new(input))?;
+        Ok(kb)
+    }
+    /// Open a knowledge base that appends everything it is told or retracts
+    /// to the journal at `journal`, before processing it.
+    /// The knowledge base is rebuilt loading the snapshot at `snapshot`, if it exists,
+    /// and then replaying the journal; the journal is created if it does not exist.
+    pub fn open<Q: AsRef<Path>>(grammar: P, journal: Q, snapshot: Option<&Path>) -> Result<KnowledgeBase<P>, SnapshotError> {
+        let (kb, base) = match snapshot.filter(|snapshot| snapshot.exists()) {
+            Some(snapshot) => {
+                let data = fs::read(snapshot)?;
+                (KnowledgeBase::load(grammar, data.as_slice())?, Some(journal::checksum(&data)))
+            },
+            None => (KnowledgeBase::new(grammar), None),
+        };
+        let journal = Journal::open(journal.as_ref(), snapshot, base, |entry, stopped| {
+            // inference that was stopped is stopped again after as many activations
+            let options = TellOptions { max_activations: stopped, ..TellOptions::default() };
+            let (text, result) = match &entry {
+                Entry::Tell(text) => (text.clone(), kb.engine().tell_with(text, &options).map(|_| ())),
+                Entry::Retract(text) => (text.clone(), kb.engine().retract(text)),
+                Entry::RemoveRule(id) => (id.to_string(), kb.engine().remove_rule(*id).map(|_| ())),
+                Entry::EnableRule(id) => (id.to_string(), kb.engine().set_rule_enabled(*id, true)),
+                Entry::DisableRule(id) => (id.to_string(), kb.engine().set_rule_enabled(*id, false)),
+                Entry::Resume => (String::from("resume"), kb.engine().resume(&options).map(|_| ())),
+                Entry::Stopped(activations) => (activations.to_string(), Ok(())),
+            };
+            // only knowledge that parsed is journaled, so it must replay
+            result.map_err(|err| SnapshotError::Journal(format!("cannot replay {:?}: {}", text, err)))
+        })?;
+        kb.engine().journal.replace(Some(journal));
+        Ok(kb)
+    }
+    /// Save a snapshot of the knowledge base in the snapshot file given to `open`,
+    /// and start the journal again from it.
+    pub fn compact(&self) -> Result<(), SnapshotError> {
+        let mut data = vec![];
+        self.save(&mut data)?;
+        match self.engine().journal.borrow_mut().as_mut() {
+            Some(journal) => journal.compact(&data),
+            None => Err(SnapshotError::Journal(String::from("the knowledge base has no journal"))),
+        }
+    }
+    /// Start or stop recording, for each derived fact, the rule that derived it
+    /// and its bindings, to be reported by `why`. It is off by default.
+    pub fn record_provenance(&self, enabled: bool) {
+        self.engine().provenance.set(enabled);
+    }
+    /// Set the order in which rules with the same salience fire,
+    /// `Strategy::Breadth` by default. It is not kept in snapshots.
+    pub fn set_strategy(&self, strategy: Strategy) {
+        self.engine().strategy.set(strategy);
+    }
+    pub fn strategy(&self) -> Strategy {
+        self.engine().strategy.get()
+    }
+    /// A proof of the given fact (with or without a trailing `◊`),
+    /// down to told facts, or `None` if the fact is not in the knowledge base.
+    /// Facts derived while provenance was not being recorded,
+    /// or loaded from a snapshot, are proven only by the facts they were derived from.
+    pub fn why(&self, fact: &str) -> Result<Option<Proof>, MPError> {
+        self.engine().why(fact)
+    }
+    /// Call `handler` with the outputs of all rules.
+    /// The returned id can be given to `remove_output` to stop calling it.
+    /// Handlers are called as the rules fire, so they must not use the knowledge base,
+    /// not even to register or remove handlers.
+    pub fn on_output<F: FnMut(&Output) + 'static>(&self, handler: F) -> HandlerId {
+        self.engine().add_output(OutputTarget::All, Box::new(handler))
+    }
+    /// Call `handler` with the outputs of the given rule,
+    /// identified by its text as it was told, regardless of whitespace,
+    /// as `on_output` does.
+    pub fn on_rule_output<F: FnMut(&Output) + 'static>(&self, rule: &str, handler: F) -> HandlerId {
+        self.engine().add_output(OutputTarget::rule(rule), Box::new(handler))
+    }
+    /// Call `handler` with the outputs that match the given fact,
+    /// which can have variables, as `on_output` does.
+    pub fn on_output_matching<F: FnMut(&Output) + 'static>(&self, pattern: &str, handler: F) -> Result<HandlerId, MPError> {
+        self.engine().add_output_pattern(pattern, Box::new(handler))
+    }
+    /// Stop calling the output handler with the given id,
+    /// and return whether it was registered.
+    pub fn remove_output(&self, id: HandlerId) -> bool {
+        let mut outputs = self.engine().outputs.borrow_mut();
+        let len = outputs.len();
+        outputs.retain(|(handler_id, _, _)| *handler_id != id);
+        outputs.len() < len
+    }
+    /// Call `callback` with each new fact that matches the given fact pattern,
+    /// which can have variables, whether told or derived, and with the bindings
+    /// of the variables in the pattern. It is called as the fact is added,
+    /// so it must not use the knowledge base.
+    /// Facts already in the knowledge base are not notified.
+    pub fn subscribe<F: FnMut(&str, &Bindings) + 'static>(&self, pattern: &str, callback: F) -> Result<(), MPError> {
+        self.engine().subscribe(pattern, Box::new(callback))
+    }
+    /// Tell knowledge, as `tell` does, but stop inference if it goes beyond the limits
+    /// in `options`. The activations left are kept, to be processed by `resume`
+    /// or along with the knowledge told next, and the returned status says whether
+    /// inference reached a fixpoint or why it was stopped.
+    /// When a journal is replayed, inference stops again after as many activations
+    /// as it processed before stopping.
+    pub fn tell_with(&self, knowledge: &str, options: &TellOptions) -> Result<Status, MPError> {
+        self.engine().tell_with(knowledge, options).map(|(_, status)| status)
+    }
+    /// Continue inference stopped by the limits given to `tell_with`,
+    /// within the limits in `options`.
+    /// It only fails if the knowledge base has a journal that cannot be written.
+    pub fn resume(&self, options: &TellOptions) -> Result<Status, MPError> {
+        self.engine().resume(options)
+    }
+    /// Tell the knowledge in the file at `path`, a sentence at a time,
+    /// as it is read. Other files can be included with
+    /// `@include "other.mp" ◊`, with paths relative to the including file.
+    /// Errors are reported with the file and the line and column in it;
+    /// the knowledge before the error is kept.
+    pub fn tell_file<Q: AsRef<Path>>(&self, path: Q) -> Result<(), MPError> {
+        let path = path.as_ref();
+        self.engine().tell_file(path, &path.display().to_string(), &mut vec![])
+    }
+    /// Tell the knowledge read from `input`, as `tell_file` does,
+    /// with included paths relative to the current directory.
+    /// Errors are reported with the line and column in the input.
+    pub fn tell_reader<R: Read>(&self, input: R) -> Result<(), MPError> {
+        self.engine().tell_input(BufReader::new(input), None, &mut vec![])
+    }
+    /// Add many facts, running inference once they are all added,
+    /// rather than after each of them, as `tell` does.
+    /// Each fact can end with `◊` or `<>`, or not.
+    /// The facts are taken, journaled and added by chunks,
+    /// so if there is an error the facts in previous chunks are kept,
+    /// and inference is run for them. The line of an error is
+    /// the position of the fact in `facts`, counting from 1.
+    pub fn load_facts<I, S>(&self, facts: I) -> Result<BulkLoad, MPError>
+        where I: IntoIterator<Item = S>, S: AsRef<str>
+    {
+        // errors are reported with the position of the fact in the iterator as their line
+        let sentences = facts.into_iter().enumerate().map(|(index, fact)| {
+            let text = fact.as_ref().trim();
+            let text = text.strip_suffix('◊').or_else(|| text.strip_suffix("<>")).unwrap_or(text);
+            Ok(Sentence { text: text.to_string(), line: index + 1, col: 1 })
+        });
+        self.engine().load_facts(sentences)
+    }
+    /// Add the facts read from `input`, as `load_facts` does,
+    /// reading them as they are added. Errors are reported
+    /// with the line and column in the input.
+    pub fn load_facts_from<R: Read>(&self, input: R) -> Result<BulkLoad, MPError> {
+        self.engine().load_facts(Sentences::new(BufReader::new(input)))
+    }
+    /// Tell knowledge without running inference;
+    /// it is left in the queues, to be processed by `step`, `run` or `resume`.
+    pub fn enqueue(&self, knowledge: &str) -> Result<(), MPError> {
+        let options = TellOptions { max_activations: Some(0), ..TellOptions::default() };
+        self.engine().tell_with(knowledge, &options).map(|_| ())
+    }
+    /// The activations waiting to be processed.
+    /// The returned reference must be dropped before telling anything
+    /// or running inference.
+    pub fn queues(&self) -> Ref<'_, Queues<'_>> {
+        self.engine().pending.borrow()
+    }
+    /// Process the next activation in the queues, if there is any.
+    /// It only fails if the knowledge base has a journal that cannot be written.
+    pub fn step(&self) -> Result<Option<Step>, MPError> {
+        self.engine().step()
+    }
+    /// Process the activations in the queues until there are none left.
+    pub fn run(&self) -> Result<Status, MPError> {
+        self.resume(&TellOptions::default())
+    }
+    /// Tell knowledge, as `tell` does, and return the ids given to the rules in it, in order.
+    pub fn tell_with_ids(&self, knowledge: &str) -> Result<Vec<RuleId>, MPError> {
+        self.engine().tell(knowledge)
+    }
+    /// The facts in the knowledge base, told or derived, sorted.
+    pub fn facts(&self) -> Vec<String> {
+        let mut facts: Vec<String> = self.engine().justifications.facts().into_iter()
+            .map(|(_, text)| text.trim().to_string())
+            .collect();
+        facts.sort();
+        facts
+    }
+    /// The rules that have been told, in the order they were told,
+    /// each with the partially matched rules obtained from it.
+    pub fn rules(&self) -> Vec<RuleInfo> {
+        self.engine().rules()
+    }
+    /// Remove the rule with the given id, along with the partially matched rules
+    /// obtained from it, so that it is not matched any more.
+    /// The facts already derived with it are kept.
+    /// Returns whether there was such a rule.
+    pub fn remove_rule(&self, id: RuleId) -> Result<bool, MPError> {
+        self.engine().remove_rule(id)
+    }
+    /// Stop matching the rules with the annotation given in `selector`,
+    /// e.g. `@name(price-alert)` or `@tag(finance)`, and return their ids.
+    /// Facts added while a rule is disabled are not matched by it
+    /// when it is enabled again, and matches of it waiting to fire are dropped.
+    pub fn disable_rules(&self, selector: &str) -> Result<Vec<RuleId>, MPError> {
+        let ids = self.engine().select_rules(selector)?;
+        for id in ids.iter() {
+            self.engine().set_rule_enabled(*id, false)?;
         }
+        Ok(ids)
+    }
+    /// Start matching again the rules with the annotation given in `selector`,
+    /// and return their ids.
+    pub fn enable_rules(&self, selector: &str) -> Result<Vec<RuleId>, MPError> {
+        let ids = self.engine().select_rules(selector)?;
+        for id in ids.iter() {
+            self.engine().set_rule_enabled(*id, true)?;
+        }
+        Ok(ids)
+    }
+    /// Remove the rules with the annotation given in `selector`, as `remove_rule` does,
+    /// and return their ids.
+    pub fn remove_rules(&self, selector: &str) -> Result<Vec<RuleId>, MPError> {
+        let ids = self.engine().select_rules(selector)?;
+        for id in ids.iter() {
+            self.engine().remove_rule(*id)?;
+        }
+        Ok(ids)
+    }
+    fn engine<'a>(&'a self) -> &'a Engine<'a, P> {
+        // the engine holds references to data that it owns,
+        // so they are valid for as long as it is borrowed
+        unsafe { mem::transmute( &*self.engine ) }
+    }
+}
+impl<P: FactGrammar + 'static> KBase for KnowledgeBase<P> {
+    fn tell(&self, knowledge: &str) -> Result<(), MPError> {
+        self.engine().tell(knowledge).map(|_| ())
+    }
+    fn ask(&self, knowledge: &str) -> Result<Vec<Bindings>, MPError> {
+        self.engine().ask(knowledge)
+    }
+    fn retract(&self, knowledge: &str) -> Result<(), MPError> {
+        self.engine().retract(knowledge)
+    }
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.engine().diagnostics.borrow().clone()
+    }
+    fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.engine().diagnostics.replace(vec![])
+    }
+}
 
-        pub struct KB<'a> {
-            mpparser: &'a MPParser<'a>,
-            tparser: TParser<'a>,
-            facts: FactSet<'a>,
-            rules: RuleSet<'a>,
+pub struct Engine<'a, P: FactGrammar> {
+    mpparser: MPParser<'a, P>,
+    tparser: TParser<'a>,
+    facts: FactSet<'a>,
+    rules: RuleSet<'a>,
+    negations: RuleSet<'a>,
+    justifications: Justifications<'a>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    journal: RefCell<Option<Journal>>,
+    provenance: Cell<bool>,
+    outputs: RefCell<Vec<(HandlerId, OutputTarget<'a>, OutputHandler)>>,
+    next_handler_id: Cell<HandlerId>,
+    output_patterns: RuleSet<'a>,
+    subscriptions: RuleSet<'a>,
+    subscribers: RefCell<HashMap<&'a str, Vec<Subscriber>>>,
+    next_rule_id: Cell<RuleId>,
+    disabled: RefCell<HashSet<RuleId>>,
+    fired: RefCell<HashMap<RuleId, usize>>,
+    strategy: Cell<Strategy>,
+    /// The activations left by inference that was stopped before reaching a fixpoint.
+    pending: RefCell<Queues<'a>>,
+}
+impl<'a, P: FactGrammar> Engine<'a, P> {
+    fn tell(&'a self, knowledge: &str) -> Result<Vec<RuleId>, MPError> {
+        self.tell_with(knowledge, &TellOptions::default()).map(|(ids, _)| ids)
+    }
+    fn tell_with(&'a self, knowledge: &str, options: &TellOptions) -> Result<(Vec<RuleId>, Status), MPError> {
+        let ParseResult { rules, facts } = self.mpparser.parse_text(knowledge.trim())?;
+        self.check_stratified(knowledge.trim(), &rules)?;
+        self.journal(Entry::Tell(knowledge.to_string()))?;
+        let mut ids = vec![];
+        let mut budget = Budget::new(options);
+        let mut status = Status::Fixpoint;
+        let mut queues = self.pending.take();
+        // once inference is stopped, the rest of the knowledge is left in the queues
+        for mut rule in rules {
+            rule.id = self.next_rule_id.get();
+            self.next_rule_id.set(rule.id + 1);
+            ids.push(rule.id);
+            let act = Activation::from_rule(rule, None, true);
+            queues.rule_queue.push_back(act);
+            if status == Status::Fixpoint {
+                (queues, status) = self.run_activations(queues, &mut budget);
+            }
         }
-        impl<'a> KBase<'a> for KB<'a> {
-            fn tell(&'a self, knowledge: &'a str) {
-                let result = self.mpparser.parse_text(knowledge.trim());
-                let mut queues = Queues::new();
-                if result.is_err() {
-                    panic!("Parsing problem! {}", result.err().unwrap());
-                } else {
-                    let ParseResult { rules, facts } = result.ok().unwrap();
-                    for rule in rules {
-                        let act = Activation::from_rule(rule, None, true);
-                        queues.rule_queue.push_back(act);
-                        queues = self.process_activations(queues);
-                    }
-                    for fact in facts {
-                        let act = Activation::from_fact(fact, None, false);
-                        queues.fact_queue.push_back(act);
-                        queues = self.process_activations(queues);
-                    }
+        for fact in facts {
+            let act = Activation::from_fact(fact, None, None, None, false);
+            queues.fact_queue.push_back(act);
+            if status == Status::Fixpoint {
+                (queues, status) = self.run_activations(queues, &mut budget);
+            }
+        }
+        if status == Status::Fixpoint && !queues.is_empty() {
+            // there was knowledge pending from a stopped call, but none was told now
+            (queues, status) = self.run_activations(queues, &mut budget);
+        }
+        self.pending.replace(queues);
+        self.journal_stop(status, &budget)?;
+        Ok((ids, status))
+    }
+    /// Check that, with the new rules, no negated antecedent depends on the rule it is in,
+    /// through the consequents of the rules that depend in turn on that rule,
+    /// since the truth of such a negation would never settle.
+    fn check_stratified(&'a self, text: &str, new_rules: &[MPRule<'a>]) -> Result<(), MPError> {
+        // the rules in the trees have lost the antecedents they have matched, so take them from their sources
+        let mut told: BTreeMap<RuleId, &'a str> = BTreeMap::new();
+        for (_, RuleRef { rule, .. }) in self.rules.rule_refs().into_iter().chain(self.negations.rule_refs()) {
+            if rule.support.is_empty() {
+                told.insert(rule.id, rule.source);
+            }
+        }
+        for activation in self.pending.borrow().rule_queue.iter() {
+            if let Activation::MPRule { rule, .. } = activation {
+                if rule.support.is_empty() {
+                    told.insert(rule.id, rule.source);
                 }
             }
-            fn ask(&'a self, knowledge: &'a str) -> Vec<MPMatching<'a>> {
-                let ParseResult { mut facts, .. } = self.mpparser.parse_text(knowledge).ok().expect("parse result");
-                let fact = facts.pop().unwrap();
-                let q = self.mpparser.parse_fact(fact);
-                let (resp, _, _) = self.facts.ask_fact(q);
-                resp
-            }
-        }
-        impl<'a> KB<'a> {
-
-            pub fn new () -> KB<'a> {
-                let mpparser = Box::leak(Box::new(MPParser::new()));
-                let root_path = mpparser.lexicon.empty_path();
-                let tparser = TParser::new(&mpparser.lexicon);
-                Self {
-                    mpparser,
-                    tparser,
-                    facts: FactSet::new(),
-                    rules: RuleSet::new(root_path),
+        }
+        let mut told_rules: Vec<MPRule<'a>> = vec![];
+        for source in told.values() {
+            told_rules.extend(self.mpparser.parse_text(&format!("{} ◊", source))?.rules);
+        }
+        let antecedents = |rule: &MPRule<'a>| -> Vec<(&'a str, bool)> {
+            let mut antecedents: Vec<(&'a str, bool)> = vec![];
+            for antecedent in std::iter::once(&rule.antecedents).chain(rule.more_antecedents.iter()) {
+                if let Some(fact) = antecedent.fact {
+                    antecedents.push((fact, antecedent.negated));
                 }
             }
-            fn process_activations(&'a self, mut queues: Queues<'a>) -> Queues<'a> {
-                loop {
-                    let mut next_opt = queues.rule_queue.pop_front();
-                    if next_opt.is_none() {
-                        next_opt = queues.match_queue.pop_front();
-                        if next_opt.is_none() {
-                            next_opt = queues.fact_queue.pop_front();
-                            if next_opt.is_none() {
-                                break
-                            }
-                        }
-                    }
-                    match next_opt.unwrap() {
-                        Activation::Fact {
-                                fact,
-                                matched,
-                                query_rules,
-                        } => {
-                            queues = self.process_fact(fact, matched, query_rules, queues);
-                        },
-                        Activation::MPRule {
-                            rule,
-                            paths,
-                            query_rules,
-                        } => {
-                            queues = self.process_rule(rule, paths, query_rules, queues);
-                        },
-                        Activation::Match {
-                            rule,
-                            matched,
-                            query_rules,
-                        } => {
-                            queues = self.process_match(rule, matched, query_rules, queues);
-                        },
+            antecedents
+        };
+        let mut rules: Vec<&MPRule<'a>> = told_rules.iter().collect();
+        if !rules.iter().cloned().chain(new_rules.iter()).any(|rule| antecedents(rule).iter().any(|(_, negated)| *negated)) {
+            return Ok(());
+        }
+        // edges from each rule to the rules with antecedents that its consequents may match,
+        // with the negated antecedent if the antecedent is negated
+        let mut edges: Vec<Vec<(usize, Option<&'a str>)>> = vec![vec![]; rules.len()];
+        let link = |rules: &[&MPRule<'a>], edges: &mut Vec<Vec<(usize, Option<&'a str>)>>, from: usize, to: usize| {
+            for consequent in rules[from].consequents.iter() {
+                for (fact, negated) in antecedents(rules[to]) {
+                    if self.mpparser.may_unify(consequent, fact) {
+                        edges[from].push((to, if negated { Some(fact) } else { None }));
                     }
                 }
-                queues
-            }
-            fn process_rule(&'a self, mut rule: MPRule<'a>, paths: Option<Vec<MPPath<'a>>>, query_rules: bool, mut queues: Queues<'a>) -> Queues<'a> {
-                
-                trace!("ADDING RULE {}", rule);
-
-                if rule.antecedents.fact.is_some() {
-                    if constants::NEW_RULES && paths.is_none() && query_rules {
-                        let (new_queues, new_paths, old_rule, unique) = self.query_rule(rule, queues);
-                        rule = old_rule;
-                        queues = new_queues;
-                    };
-                    let MPRule {
-                        antecedents: Antecedents {
-                            fact,
-                            transforms,
-                            conditions,
-                        },
-                        more_antecedents,
-                        consequents,
-                        mut matched,
-                        output,
-                    } = rule;
-                    let ant = fact.unwrap();
-
-                    let mut new_antecedent: Vec<MPPath<'a>>;
-                    if paths.is_none() {
-                        new_antecedent = self.mpparser.parse_fact(ant);
-                        if matched.len() > 0 {
-                            let (new_ant, old_matched, _) = self.mpparser.substitute_fact(new_antecedent, matched);
-                            new_antecedent = new_ant;
-                            matched = old_matched;
+            }
+        };
+        for from in 0..rules.len() {
+            for to in 0..rules.len() {
+                link(&rules, &mut edges, from, to);
+            }
+        }
+        // the told rules are stratified, so any cycle goes through the rule just added
+        for rule in new_rules {
+            let new = rules.len();
+            rules.push(rule);
+            edges.push(vec![]);
+            for other in 0..new {
+                link(&rules, &mut edges, new, other);
+                link(&rules, &mut edges, other, new);
+            }
+            link(&rules, &mut edges, new, new);
+            for (from, targets) in edges.iter().enumerate() {
+                for (to, negation) in targets {
+                    if let Some(fact) = negation {
+                        if reaches(&edges, *to, from) {
+                            let offset = text.find(rule.source).unwrap_or(0);
+                            let (line, col) = line_col(text, offset);
+                            return Err(MPError::KnowledgeSyntax {
+                                text: text.to_string(),
+                                line,
+                                col,
+                                message: format!("the rules are not stratified: the negation of {} depends on facts derived relying on it", fact.trim()),
+                            });
                         }
-                    } else {
-                        new_antecedent = paths.unwrap();
                     }
-                    let (varmap, normal_ant) = self.mpparser.normalize_fact(new_antecedent);
-
-                    let rule = MPRule {
-                        antecedents: Antecedents {
-                            fact: None,
-                            transforms,
-                            conditions,
-                        },
-                        more_antecedents,
-                        consequents,
-                        matched,
-                        output,
-                    };
-                    let rule_ref = RuleRef {
-                        rule: rule,
-                        varmap,
-                    };
-                    self.rules.follow_and_create_paths(normal_ant, rule_ref, 1);
-                } else {
-                    queues.match_queue.push_back(Activation::from_matching(rule, None, query_rules));
                 }
-                queues
             }
-            fn process_fact(&'a self,
-                            fact: &'a str,
-                            matching: Option<MPMatching<'a>>,
-                            query_rules: bool,
-                            mut queues: Queues<'a>) -> Queues<'a> {
-
-
-                let mut fact_paths = self.mpparser.parse_fact(fact);
-                let mut fact_string: Option<String> = None;
-                if matching.is_some() {
-                    let (new_fact_paths, _, fact_str) = self.mpparser.substitute_fact(fact_paths, matching.unwrap());
-                    fact_paths = new_fact_paths;
-                    fact_string = fact_str;
+        }
+        Ok(())
+    }
+    fn step(&'a self) -> Result<Option<Step>, MPError> {
+        if self.pending.borrow().is_empty() {
+            return Ok(None);
+        }
+        // a step is journaled as inference resumed and stopped after one activation
+        self.journal(Entry::Resume)?;
+        let mut queues = self.pending.take();
+        let next = queues.pop_next().expect("the queues are not empty");
+        let activation = next.to_string();
+        let (rules, matches, facts) = (queues.rule_queue.len(), queues.match_queue.len(), queues.fact_queue.len());
+        let activated = queues.agenda.activated();
+        queues = self.process_activation(next, queues);
+        // processing only adds activations at the back of the queues
+        let enqueued = queues.rule_queue.iter().skip(rules)
+            .chain(queues.match_queue.iter().skip(matches))
+            .chain(queues.fact_queue.iter().skip(facts))
+            .chain(queues.agenda.pushed_after(activated))
+            .map(|activation| activation.to_string())
+            .collect();
+        let stopped = !queues.is_empty();
+        self.pending.replace(queues);
+        if stopped {
+            self.journal(Entry::Stopped(1))?;
+        }
+        Ok(Some(Step { activation, enqueued }))
+    }
+    fn load_facts<I>(&'a self, sentences: I) -> Result<BulkLoad, MPError>
+        where I: Iterator<Item = Result<Sentence, MPError>>
+    {
+        let mut queues = self.pending.take();
+        // the facts added before an error stay, so inference is run for them
+        let loaded = self.load_chunks(sentences, &mut queues);
+        self.pending.replace(self.process_activations(queues));
+        loaded
+    }
+    fn load_chunks<I>(&'a self, mut sentences: I, queues: &mut Queues<'a>) -> Result<BulkLoad, MPError>
+        where I: Iterator<Item = Result<Sentence, MPError>>
+    {
+        let mut loaded = BulkLoad::default();
+        loop {
+            let mut chunk = vec![];
+            for sentence in sentences.by_ref().take(constants::BULK_CHUNK) {
+                let Sentence { text, line, col } = sentence?;
+                let paths = self.mpparser.parse_fact(text.trim()).map_err(|err| err.shifted((line, col)))?;
+                chunk.push((self.mpparser.factstr.intern(text.trim()), paths));
+            }
+            if chunk.is_empty() {
+                return Ok(loaded);
+            }
+            let text: Vec<&str> = chunk.iter().map(|(fact, _)| *fact).collect();
+            self.journal(Entry::Tell(format!("{} ◊", text.join(" ◊\n"))))?;
+            for (fact, paths) in chunk {
+                let (new_queues, added) = self.add_fact(fact, paths, None, None, None, false, mem::take(queues));
+                *queues = new_queues;
+                if added {
+                    loaded.added += 1;
+                } else {
+                    loaded.duplicates += 1;
                 }
-                let (exists, paths) = self.facts.ask_fact_bool(fact_paths);
-                fact_paths = paths;
-                if  exists {
-                    return queues;
+                if queues.len() > constants::BULK_QUEUE {
+                    *queues = self.process_activations(mem::take(queues));
                 }
-                let (response, paths) = self.rules.query_paths(fact_paths);
-                fact_paths = paths;
-                for (rule_refs, matching) in response {
-                    for rule_ref in rule_refs.borrow().iter() {
-                        let real_matching = get_real_matching(&matching, &rule_ref.varmap); 
-                        queues.match_queue.push_back(Activation::from_matching(rule_ref.rule.clone(), Some(real_matching), query_rules));
+            }
+        }
+    }
+    /// Tell the sentences read from `input`, one at a time,
+    /// reading the files included from `file`, if given, relative to it.
+    /// `reading` holds the files being read, to detect include cycles.
+    fn tell_input<R: BufRead>(&'a self, input: R, file: Option<&Path>, reading: &mut Vec<PathBuf>) -> Result<(), MPError> {
+        let in_file = |err: MPError| match file {
+            Some(file) => err.in_file(file.to_path_buf()),
+            None => err,
+        };
+        for sentence in Sentences::new(input) {
+            let Sentence { text, line, col } = sentence.map_err(in_file)?;
+            let told = if text.starts_with("@include") {
+                self.mpparser.parse_include(&text).and_then(|include| {
+                    let dir = file.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+                    self.tell_file(&dir.join(include), &text, reading)
+                })
+            } else {
+                self.tell(&format!("{} ◊", text)).map(|_| ())
+            };
+            told.map_err(|err| in_file(err.shifted((line, col))))?;
+        }
+        Ok(())
+    }
+    /// Tell the knowledge in the file at `path`, included with `directive`.
+    fn tell_file(&'a self, path: &Path, directive: &str, reading: &mut Vec<PathBuf>) -> Result<(), MPError> {
+        let unreadable = |err: std::io::Error| MPError::Read {
+            text: directive.to_string(),
+            line: 1,
+            col: 1,
+            message: format!("cannot read {}: {}", path.display(), err),
+        };
+        let canonical = fs::canonicalize(path).map_err(unreadable)?;
+        if let Some(first) = reading.iter().position(|file| *file == canonical) {
+            let mut cycle: Vec<String> = reading[first..].iter().map(|file| file.display().to_string()).collect();
+            cycle.push(canonical.display().to_string());
+            return Err(MPError::KnowledgeSyntax {
+                text: directive.to_string(),
+                line: 1,
+                col: 1,
+                message: format!("include cycle: {}", cycle.join(" → ")),
+            });
+        }
+        let input = fs::File::open(path).map_err(unreadable)?;
+        reading.push(canonical);
+        let told = self.tell_input(BufReader::new(input), Some(path), reading);
+        reading.pop();
+        told
+    }
+    fn resume(&'a self, options: &TellOptions) -> Result<Status, MPError> {
+        if self.pending.borrow().is_empty() {
+            return Ok(Status::Fixpoint);
+        }
+        self.journal(Entry::Resume)?;
+        let mut budget = Budget::new(options);
+        let (queues, status) = self.run_activations(self.pending.take(), &mut budget);
+        self.pending.replace(queues);
+        self.journal_stop(status, &budget)?;
+        Ok(status)
+    }
+    fn ask(&'a self, knowledge: &str) -> Result<Vec<Bindings>, MPError> {
+        let query = self.mpparser.parse_query(knowledge.trim())?;
+        let mut resp: Vec<MPMatching<'a>> = vec![HashMap::new()];
+        for (q, antecedents) in query {
+            let mut joined = vec![];
+            for matching in resp {
+                let (new_resp, _) = self.facts.ask_fact_matching(q.clone(), matching);
+                for mut new_matching in new_resp {
+                    if self.filter_query_matching(&antecedents, &mut new_matching) {
+                        joined.push(new_matching);
                     }
                 }
-                if fact_string.is_some() {
-                    info!("ADDING FACT: {}", &fact_string.unwrap());
-                } else {
-                    info!("ADDING FACT: {}", &fact);
+            }
+            resp = joined;
+            if resp.is_empty() {
+                break;
+            }
+        }
+        Ok(resp.iter().map(Bindings::from_matching).collect())
+    }
+    fn retract(&'a self, knowledge: &str) -> Result<(), MPError> {
+        let ParseResult { rules, facts } = self.mpparser.parse_text(knowledge.trim())?;
+        if !rules.is_empty() {
+            return Err(MPError::KnowledgeSyntax {
+                text: knowledge.to_string(),
+                line: 1,
+                col: 1,
+                message: String::from("only facts can be retracted"),
+            });
+        }
+        self.journal(Entry::Retract(knowledge.to_string()))?;
+        let mut queues = Queues::new();
+        for fact in facts {
+            let paths = self.mpparser.parse_fact(fact)?;
+            let lost = self.justifications.retract(tms::fact_key(&paths));
+            queues = self.remove_facts(lost, queues);
+            queues = self.process_activations(queues);
+        }
+        Ok(())
+    }
+    fn rules(&'a self) -> Vec<RuleInfo> {
+        let mut rules: BTreeMap<RuleId, RuleInfo> = BTreeMap::new();
+        let mut partial = vec![];
+        for (_, RuleRef { rule, .. }) in self.rules.rule_refs().into_iter().chain(self.negations.rule_refs()) {
+            // only the rules as told have no support
+            if rule.support.is_empty() {
+                rules.insert(rule.id, RuleInfo {
+                    id: rule.id,
+                    name: rule.meta.name.map(String::from),
+                    tags: rule.meta.tags.iter().map(|tag| tag.to_string()).collect(),
+                    salience: rule.meta.salience,
+                    source: rule.source.trim().to_string(),
+                    enabled: !self.disabled.borrow().contains(&rule.id),
+                    fired: self.fired.borrow().get(&rule.id).cloned().unwrap_or(0),
+                    partial: vec![],
+                });
+            } else {
+                partial.push(rule);
+            }
+        }
+        for rule in partial {
+            if let Some(info) = rules.get_mut(&rule.id) {
+                info.partial.push(PartialRule {
+                    remaining: rule.more_antecedents.len() + 1,
+                    bindings: Bindings::from_matching(&rule.matched),
+                });
+            }
+        }
+        for info in rules.values_mut() {
+            info.partial.sort_by(|a, b| {
+                b.remaining.cmp(&a.remaining).then_with(|| a.bindings.iter().cmp(b.bindings.iter()))
+            });
+        }
+        rules.into_values().collect()
+    }
+    fn remove_rule(&'a self, id: RuleId) -> Result<bool, MPError> {
+        self.journal(Entry::RemoveRule(id))?;
+        let removed = self.rules.remove_rule_refs(|rule_ref| rule_ref.rule.id == id)
+            + self.negations.remove_rule_refs(|rule_ref| rule_ref.rule.id == id);
+        // activations of the rule waiting from a stopped call must not fire it later
+        let purged = self.pending.borrow_mut().purge_rule(id);
+        self.disabled.borrow_mut().remove(&id);
+        self.fired.borrow_mut().remove(&id);
+        Ok(removed + purged > 0)
+    }
+    fn set_rule_enabled(&'a self, id: RuleId, enabled: bool) -> Result<(), MPError> {
+        if enabled {
+            self.journal(Entry::EnableRule(id))?;
+            self.disabled.borrow_mut().remove(&id);
+        } else {
+            self.journal(Entry::DisableRule(id))?;
+            self.disabled.borrow_mut().insert(id);
+        }
+        Ok(())
+    }
+    /// The ids of the told rules with the annotation given in `selector`, e.g. `@tag(finance)`.
+    fn select_rules(&'a self, selector: &str) -> Result<Vec<RuleId>, MPError> {
+        let (key, value) = self.mpparser.parse_selector(selector)?;
+        Ok(self.rules().into_iter()
+            .filter(|rule| match key.as_str() {
+                "name" => rule.name.as_deref() == Some(value.as_str()),
+                "salience" => value.parse() == Ok(rule.salience),
+                _ => rule.tags.contains(&value),
+            })
+            .map(|rule| rule.id)
+            .collect())
+    }
+    fn why(&'a self, fact: &str) -> Result<Option<Proof>, MPError> {
+        let fact = fact.trim();
+        let fact = fact.strip_suffix('◊').unwrap_or(fact).trim_end();
+        let paths = self.mpparser.parse_fact(fact)?;
+        Ok(self.justifications.proof(tms::fact_key(&paths)))
+    }
+    fn journal(&self, entry: Entry) -> Result<(), MPError> {
+        if let Some(journal) = self.journal.borrow_mut().as_mut() {
+            if let Err(err) = journal.append(&entry) {
+                let text = match entry {
+                    Entry::Tell(text) | Entry::Retract(text) => text,
+                    Entry::RemoveRule(id) | Entry::EnableRule(id) | Entry::DisableRule(id) => id.to_string(),
+                    Entry::Resume => String::from("resume"),
+                    Entry::Stopped(activations) => activations.to_string(),
+                };
+                return Err(MPError::journal(&text, err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Record in the journal where inference stopped, if it did not reach a fixpoint,
+    /// so that replaying the journal stops at the same point.
+    fn journal_stop(&self, status: Status, budget: &Budget) -> Result<(), MPError> {
+        if status == Status::Fixpoint {
+            return Ok(());
+        }
+        self.journal(Entry::Stopped(budget.used()))
+    }
+    fn save<W: Write>(&'a self, out: W) -> Result<(), SnapshotError> {
+        let mut enc = Encoder::new();
+        let facts = self.justifications.facts();
+        enc.count(facts.len());
+        for (key, text) in facts {
+            let paths = self.mpparser.parse_fact(text).expect("facts in the knowledge base parse");
+            enc.u64(key);
+            enc.str(text);
+            enc.paths(&paths);
+        }
+        enc.keys(&self.justifications.told());
+        let supports = self.justifications.supports();
+        enc.count(supports.len());
+        for (key, justifications) in supports {
+            enc.u64(key);
+            enc.count(justifications.len());
+            for support in justifications {
+                enc.keys(&support);
+            }
+        }
+        let absent = self.justifications.absent();
+        enc.count(absent.len());
+        for (key, text) in absent {
+            enc.u64(key);
+            enc.str(text);
+        }
+        let (clock, times) = self.justifications.stamps();
+        enc.u64(clock);
+        enc.count(times.len());
+        for (key, time) in times {
+            enc.u64(key);
+            enc.u64(time);
+        }
+        self.save_rules(&self.rules, &mut enc);
+        self.save_rules(&self.negations, &mut enc);
+        enc.u64(self.next_rule_id.get());
+        let mut disabled: Vec<RuleId> = self.disabled.borrow().iter().cloned().collect();
+        disabled.sort_unstable();
+        enc.keys(&disabled);
+        let mut fired: Vec<(RuleId, usize)> = self.fired.borrow().iter().map(|(id, n)| (*id, *n)).collect();
+        fired.sort_unstable();
+        enc.count(fired.len());
+        for (id, n) in fired {
+            enc.u64(id);
+            enc.u64(n as u64);
+        }
+        // the activations left when inference was stopped
+        let pending = self.pending.borrow();
+        for queue in [&pending.rule_queue, &pending.match_queue, &pending.fact_queue] {
+            enc.count(queue.len());
+            for activation in queue {
+                self.save_activation(activation, &mut enc);
+            }
+        }
+        pending.agenda.save(&mut enc, |activation, enc| self.save_activation(activation, enc));
+        enc.finish(self.mpparser.grammar.source(), out)
+    }
+    fn save_rules(&'a self, rules: &'a RuleSet<'a>, enc: &mut Encoder<'a>) {
+        let rule_refs = rules.rule_refs();
+        enc.count(rule_refs.len());
+        for (paths, RuleRef { rule, varmap }) in rule_refs {
+            enc.paths(&paths);
+            enc.matching(&varmap);
+            self.save_rule(&rule, enc);
+        }
+    }
+    fn save_rule(&self, rule: &MPRule<'a>, enc: &mut Encoder<'a>) {
+        enc.str(rule.source);
+        enc.count(rule.more_antecedents.len());
+        enc.bool(rule.antecedents.fact.is_some());
+        enc.matching(&rule.matched);
+        enc.keys(&rule.support);
+        enc.u64(rule.id);
+    }
+    fn save_activation(&self, activation: &Activation<'a>, enc: &mut Encoder<'a>) {
+        match activation {
+            Activation::MPRule { rule, paths, query_rules } => {
+                enc.u32(0);
+                self.save_rule(rule, enc);
+                enc.bool(paths.is_some());
+                if let Some(paths) = paths {
+                    enc.paths(paths);
                 }
-                self.facts.add_fact(fact_paths);
-                queues
-            }
-            fn process_match(&'a self,
-                             mut rule: MPRule<'a>,
-                             matching: Option<MPMatching<'a>>,
-                             mut query_rules: bool,
-                             mut queues: Queues<'a>) -> Queues<'a> {
-                let old_len = rule.more_antecedents.len();
-                let (nrule, new, passed, _) = self.preprocess_matched_rule(rule, matching);
-                if !passed {
-                    return queues;
+                enc.bool(*query_rules);
+            },
+            Activation::Fact { fact, matched, support, rule, query_rules } => {
+                enc.u32(1);
+                enc.str(fact);
+                enc.bool(matched.is_some());
+                if let Some(matched) = matched {
+                    enc.matching(matched);
                 }
-                rule = nrule;
-
-                if new {
-                    if rule.more_antecedents.len() < old_len {
-                        query_rules = true;
-                    }
-                    let mut paths: Option<Vec<MPPath>> = None;
-                    let mut add_rule = true;
-                    if query_rules {
-                        let (new_queues, new_paths, old_rule, unique) = self.query_rule(rule, queues);
-                        paths = new_paths;
-                        queues = new_queues;
-                        rule = old_rule;
-                        add_rule = !unique;
-                    }
-                    if add_rule {
-                        queues.rule_queue.push_back(Activation::from_rule(rule, paths, query_rules));
-                    }
-                } else {
-                    for consequent in rule.consequents{
-                        queues.fact_queue.push_back(Activation::from_fact(consequent, Some(rule.matched.clone()), query_rules));
-                    }
-                    if rule.output.is_some() {
-                        let pre_output = self.mpparser.parse_fact(rule.output.unwrap());
-                        let (_, _, output) = self.mpparser.substitute_fact(pre_output, rule.matched);
-                        if output.is_some() {
-                            println!("ADDING FACT: {}", &output.unwrap());
-                        } else {
-                            println!("ADDING FACT: {}", rule.output.as_ref().unwrap());
-                        }
+                enc.bool(support.is_some());
+                if let Some(support) = support {
+                    enc.keys(support);
+                }
+                enc.bool(rule.is_some());
+                if let Some((source, name)) = rule {
+                    enc.str(source);
+                    enc.bool(name.is_some());
+                    if let Some(name) = name {
+                        enc.str(name);
                     }
                 }
-                queues
-            }
-            fn query_rule(&'a self,
-                          mut rule: MPRule<'a>,
-                          mut queues: Queues<'a>) -> (Queues<'a>, Option<Vec<MPPath<'a>>>, MPRule<'a>, bool) {
-
-                let mut paths: Option<Vec<MPPath>> = None;
-                let mut unique = false;
-                if rule.antecedents.fact.is_some() {
-                    let MPRule {
-                        mut antecedents,
-                        mut more_antecedents,
-                        consequents,
-                        mut matched,
-                        output,
-                    } = rule;
-                    let fact_str = antecedents.fact.as_ref().unwrap();
-                    let mut pre_ant = self.mpparser.parse_fact(fact_str);
-                    if matched.len() > 0 {
-                        let (new_pre_ant, old_matched, _) = self.mpparser.substitute_fact(pre_ant, matched);
-                        pre_ant = new_pre_ant;
+                enc.bool(*query_rules);
+            },
+            Activation::Match { rule, matched, query_rules } => {
+                enc.u32(2);
+                self.save_rule(rule, enc);
+                enc.bool(matched.is_some());
+                if let Some(matched) = matched {
+                    enc.matching(matched);
+                }
+                enc.bool(*query_rules);
+            },
+            Activation::Firing { rule, query_rules } => {
+                enc.u32(3);
+                self.save_rule(rule, enc);
+                enc.bool(*query_rules);
+            },
+        }
+    }
+    fn restore<R: Read>(&'a self, input: R) -> Result<(), SnapshotError> {
+        let mut dec = Decoder::new(input, self.mpparser.grammar.source(), &self.mpparser.lexicon)?;
+        let n = dec.count()?;
+        for _ in 0..n {
+            let key = dec.u64()?;
+            let text = dec.string()?;
+            let paths = dec.paths()?;
+            if tms::fact_key(&paths) != key {
+                return Err(SnapshotError::Format(String::from("fact keys differ, the snapshot was written by an incompatible build")));
+            }
+            self.justifications.add_text(key, self.mpparser.factstr.intern(&text));
+            self.facts.add_fact(paths);
+        }
+        for key in dec.keys()? {
+            self.justifications.tell(key);
+        }
+        let n = dec.count()?;
+        for _ in 0..n {
+            let key = dec.u64()?;
+            let m = dec.count()?;
+            for _ in 0..m {
+                self.justifications.justify(key, dec.keys()?);
+            }
+        }
+        let n = dec.count()?;
+        for _ in 0..n {
+            let key = dec.u64()?;
+            let text = dec.string()?;
+            self.justifications.add_absent(key, self.mpparser.factstr.intern(&text));
+        }
+        let clock = dec.u64()?;
+        let n = dec.count()?;
+        let mut times = vec![];
+        for _ in 0..n {
+            times.push((dec.u64()?, dec.u64()?));
+        }
+        self.justifications.restore_stamps(clock, times);
+        // rules are saved with their source, and the position of the antecedent they are waiting for,
+        // so the compiled transforms and conditions are obtained parsing the source again
+        let mut parsed: HashMap<String, MPRule<'a>> = HashMap::new();
+        self.restore_rules(&self.rules, &mut dec, &mut parsed)?;
+        self.restore_rules(&self.negations, &mut dec, &mut parsed)?;
+        self.next_rule_id.set(dec.u64()?);
+        self.disabled.borrow_mut().extend(dec.keys()?);
+        let n = dec.count()?;
+        for _ in 0..n {
+            let id = dec.u64()?;
+            let fired = dec.u64()? as usize;
+            self.fired.borrow_mut().insert(id, fired);
+        }
+        let mut pending = Queues::new();
+        for queue in [&mut pending.rule_queue, &mut pending.match_queue, &mut pending.fact_queue] {
+            let n = dec.count()?;
+            for _ in 0..n {
+                queue.push_back(self.restore_activation(&mut dec, &mut parsed)?);
+            }
+        }
+        pending.agenda = Agenda::restore(&mut dec, |dec| self.restore_activation(dec, &mut parsed))?;
+        self.pending.replace(pending);
+        Ok(())
+    }
+    fn restore_rules<R: Read>(
+        &'a self,
+        rules: &'a RuleSet<'a>,
+        dec: &mut Decoder<'a, R>,
+        parsed: &mut HashMap<String, MPRule<'a>>,
+    ) -> Result<(), SnapshotError> {
+        let n = dec.count()?;
+        for _ in 0..n {
+            let paths = dec.paths()?;
+            let varmap = dec.matching()?;
+            let rule = self.restore_rule(dec, parsed)?;
+            let support = rule.support.clone();
+            let node = rules.follow_and_create_paths(paths, RuleRef { rule, varmap }, 1);
+            if !support.is_empty() {
+                self.justifications.derive_rule(&support, node);
+            }
+        }
+        Ok(())
+    }
+    fn restore_rule<R: Read>(&'a self, dec: &mut Decoder<'a, R>, parsed: &mut HashMap<String, MPRule<'a>>) -> Result<MPRule<'a>, SnapshotError> {
+        let source = dec.string()?;
+        let remaining = dec.count()?;
+        let has_fact = dec.bool()?;
+        let matched = dec.matching()?;
+        let support = dec.keys()?;
+        let id = dec.u64()?;
+        if !parsed.contains_key(&source) {
+            let result = self.mpparser.parse_text(&format!("{} ◊", source))
+                .map_err(|err| SnapshotError::Format(format!("bad rule: {}", err)))?;
+            let rule = result.rules.into_iter().next()
+                .ok_or_else(|| SnapshotError::Format(format!("bad rule: {}", source)))?;
+            parsed.insert(source.clone(), rule);
+        }
+        let original = &parsed[&source];
+        let mut antecedents = vec![original.antecedents.clone()];
+        antecedents.extend(original.more_antecedents.iter().cloned());
+        if remaining >= antecedents.len() {
+            return Err(SnapshotError::Format(format!("bad antecedent position in rule: {}", source)));
+        }
+        let more_antecedents: VecDeque<Antecedents<'a>> = antecedents.split_off(antecedents.len() - remaining).into();
+        let mut current = antecedents.pop().expect("an antecedent");
+        if !has_fact {
+            current.fact = None;
+        }
+        Ok(MPRule {
+            antecedents: current,
+            more_antecedents,
+            consequents: original.consequents.clone(),
+            matched,
+            output: original.output,
+            source: original.source,
+            support,
+            id,
+            meta: original.meta.clone(),
+        })
+    }
+    fn restore_activation<R: Read>(&'a self, dec: &mut Decoder<'a, R>, parsed: &mut HashMap<String, MPRule<'a>>) -> Result<Activation<'a>, SnapshotError> {
+        let activation = match dec.u32()? {
+            0 => {
+                let rule = self.restore_rule(dec, parsed)?;
+                let paths = if dec.bool()? { Some(dec.paths()?) } else { None };
+                Activation::from_rule(rule, paths, dec.bool()?)
+            },
+            1 => {
+                let fact = self.mpparser.factstr.intern(&dec.string()?);
+                let matched = if dec.bool()? { Some(dec.matching()?) } else { None };
+                let support = if dec.bool()? { Some(dec.keys()?) } else { None };
+                let rule = if dec.bool()? {
+                    let source = self.mpparser.factstr.intern(&dec.string()?);
+                    let name = if dec.bool()? { Some(self.mpparser.factstr.intern(&dec.string()?)) } else { None };
+                    Some((source, name))
+                } else {
+                    None
+                };
+                Activation::from_fact(fact, matched, support, rule, dec.bool()?)
+            },
+            2 => {
+                let rule = self.restore_rule(dec, parsed)?;
+                let matched = if dec.bool()? { Some(dec.matching()?) } else { None };
+                Activation::from_matching(rule, matched, dec.bool()?)
+            },
+            3 => {
+                let rule = self.restore_rule(dec, parsed)?;
+                Activation::from_firing(rule, dec.bool()?)
+            },
+            kind => return Err(SnapshotError::Format(format!("bad activation kind {}", kind))),
+        };
+        Ok(activation)
+    }
+    fn new (grammar: P) -> Engine<'a, P> {
+        let mpparser = MPParser::new(grammar);
+        // the lexicon is boxed, so it stays put when the parser is moved
+        let lexicon: &'a Lexicon = unsafe { mem::transmute( &*mpparser.lexicon ) };
+        let root_path = lexicon.empty_path();
+        let negations_root_path = lexicon.empty_path();
+        let tparser = TParser::new(lexicon);
+        Self {
+            mpparser,
+            tparser,
+            facts: FactSet::new(),
+            rules: RuleSet::new(root_path),
+            negations: RuleSet::new(negations_root_path),
+            justifications: Justifications::new(),
+            diagnostics: RefCell::new(vec![]),
+            journal: RefCell::new(None),
+            provenance: Cell::new(false),
+            outputs: RefCell::new(vec![]),
+            next_handler_id: Cell::new(1),
+            output_patterns: RuleSet::new(lexicon.empty_path()),
+            subscriptions: RuleSet::new(lexicon.empty_path()),
+            subscribers: RefCell::new(HashMap::new()),
+            next_rule_id: Cell::new(1),
+            disabled: RefCell::new(HashSet::new()),
+            fired: RefCell::new(HashMap::new()),
+            strategy: Cell::new(Strategy::default()),
+            pending: RefCell::new(Queues::new()),
+        }
+    }
+    fn process_activations(&'a self, queues: Queues<'a>) -> Queues<'a> {
+        let (queues, _) = self.run_activations(queues, &mut Budget::new(&TellOptions::default()));
+        queues
+    }
+    /// Process activations until there are none left, or the budget stops inference.
+    fn run_activations(&'a self, mut queues: Queues<'a>, budget: &mut Budget) -> (Queues<'a>, Status) {
+        // empty queues are a fixpoint, however much budget was used
+        while !queues.is_empty() {
+            if let Some(status) = budget.stop() {
+                return (queues, status);
+            }
+            let next = queues.pop_next().expect("the queues are not empty");
+            queues = self.process_activation(next, queues);
+        }
+        (queues, Status::Fixpoint)
+    }
+    fn process_activation(&'a self, activation: Activation<'a>, queues: Queues<'a>) -> Queues<'a> {
+        match activation {
+            Activation::Fact {
+                    fact,
+                    matched,
+                    support,
+                    rule,
+                    query_rules,
+            } => self.process_fact(fact, matched, support, rule, query_rules, queues),
+            Activation::MPRule {
+                rule,
+                paths,
+                query_rules,
+            } => self.process_rule(rule, paths, query_rules, queues),
+            Activation::Match {
+                rule,
+                matched,
+                query_rules,
+            } => self.process_match(rule, matched, query_rules, queues),
+            Activation::Firing {
+                rule,
+                query_rules,
+            } => self.fire(rule, query_rules, queues),
+        }
+    }
+    fn process_rule(&'a self, mut rule: MPRule<'a>, paths: Option<Vec<MPPath<'a>>>, query_rules: bool, mut queues: Queues<'a>) -> Queues<'a> {
+
+        trace!("ADDING RULE {}", rule);
+
+        if rule.antecedents.negated && rule.antecedents.fact.is_some() {
+            return self.process_negation(rule, queues);
+        }
+        if rule.antecedents.fact.is_some() {
+            if constants::NEW_RULES && paths.is_none() && query_rules {
+                let (new_queues, _, old_rule, _) = self.query_rule(rule, queues);
+                rule = old_rule;
+                queues = new_queues;
+            };
+            let MPRule {
+                antecedents: Antecedents {
+                    fact,
+                    negated,
+                    transforms,
+                    conditions,
+                },
+                more_antecedents,
+                consequents,
+                mut matched,
+                output,
+                source,
+                support,
+                id,
+                meta,
+            } = rule;
+            let ant = fact.unwrap();
+
+            let new_antecedent = match paths {
+                Some(paths) => paths,
+                None => {
+                    let mut new_antecedent = self.mpparser.parse_fact(ant).expect("checked when told");
+                    if !matched.is_empty() {
+                        let (new_ant, old_matched, _) = self.mpparser.substitute_fact(new_antecedent, matched).expect("checked when matched");
+                        new_antecedent = new_ant;
                         matched = old_matched;
                     }
-                    rule = MPRule {
-                        antecedents,
-                        more_antecedents,
-                        consequents,
-                        matched,
-                        output,
-                    };
-                    let (resps, old_paths, new_unique) = self.facts.ask_fact(pre_ant);
-                    unique = new_unique;
-                    paths = Some(old_paths);
-                    for resp in resps {
-                        let mut new_rule = rule.clone();
-                        new_rule.antecedents.fact = None;
-                        queues.match_queue.push_back(Activation::from_matching(new_rule, Some(resp), true));
-                    }
+                    new_antecedent
+                },
+            };
+            let (varmap, normal_ant) = self.mpparser.normalize_fact(new_antecedent);
+
+            let rule = MPRule {
+                antecedents: Antecedents {
+                    fact: None,
+                    negated,
+                    transforms,
+                    conditions,
+                },
+                more_antecedents,
+                consequents,
+                matched,
+                output,
+                source,
+                support: support.clone(),
+                id,
+                meta,
+            };
+            let rule_ref = RuleRef {
+                rule,
+                varmap,
+            };
+            let node = self.rules.follow_and_create_paths(normal_ant, rule_ref, 1);
+            if !support.is_empty() {
+                self.justifications.derive_rule(&support, node);
+            }
+        } else {
+            queues.match_queue.push_back(Activation::from_matching(rule, None, query_rules));
+        }
+        queues
+    }
+    fn process_fact(&'a self,
+                    fact: &'a str,
+                    matching: Option<MPMatching<'a>>,
+                    support: Option<Vec<FactKey>>,
+                    rule: Option<(&'a str, Option<&'a str>)>,
+                    query_rules: bool,
+                    queues: Queues<'a>) -> Queues<'a> {
+        let fact_paths = self.mpparser.parse_fact(fact).expect("checked when told");
+        let (queues, _) = self.add_fact(fact, fact_paths, matching, support, rule, query_rules, queues);
+        queues
+    }
+    /// Add a fact, already parsed, and return whether it was new.
+    #[allow(clippy::too_many_arguments)]
+    fn add_fact(&'a self,
+                fact: &'a str,
+                mut fact_paths: Vec<MPPath<'a>>,
+                matching: Option<MPMatching<'a>>,
+                support: Option<Vec<FactKey>>,
+                rule: Option<(&'a str, Option<&'a str>)>,
+                query_rules: bool,
+                mut queues: Queues<'a>) -> (Queues<'a>, bool) {
+        let mut fact_string: Option<String> = None;
+        let derivation = match (rule, &matching) {
+            (Some(rule), Some(matching)) if self.provenance.get() => Some((rule, Bindings::from_matching(matching))),
+            _ => None,
+        };
+        if let Some(matching) = matching {
+            let (new_fact_paths, _, fact_str) = self.mpparser.substitute_fact(fact_paths, matching).expect("checked when matched");
+            fact_paths = new_fact_paths;
+            fact_string = fact_str;
+        }
+        let key = tms::fact_key(&fact_paths);
+        match support {
+            Some(support) => {
+                if let Some((rule, bindings)) = derivation {
+                    self.justifications.explain(key, rule, &support, bindings);
                 }
-                (queues, paths, rule, unique)
-            }
-            fn preprocess_matched_rule(&'a self,
-                                       rule: MPRule<'a>,
-                                       mut matching: Option<MPMatching<'a>>) -> (MPRule<'a>, bool, bool, Option<MPMatching<'a>>) {
-                let MPRule {
-                    mut antecedents,
-                    mut more_antecedents,
-                    consequents,
-                    mut matched,
-                    output,
-                } = rule;
-
-                if matching.is_some() {
-                    let m = matching.unwrap();
-                    matched.extend(&m);
-                    matching = Some(m);
+                self.justifications.justify(key, support);
+            },
+            None => self.justifications.tell(key),
+        }
+        let (exists, paths) = self.facts.ask_fact_bool(fact_paths);
+        fact_paths = paths;
+        if  exists {
+            return (queues, false);
+        }
+        let text = match &fact_string {
+            Some(fact_str) => self.mpparser.factstr.intern(fact_str),
+            None => fact,
+        };
+        self.justifications.add_text(key, text);
+        self.justifications.stamp(key);
+        let (response, paths) = self.rules.query_paths(fact_paths);
+        fact_paths = paths;
+        let (negations, paths) = self.negations.query_paths(fact_paths);
+        fact_paths = paths;
+        let (subscriptions, paths) = self.subscriptions.query_paths(fact_paths);
+        fact_paths = paths;
+        if !subscriptions.is_empty() {
+            self.notify_subscribers(text, subscriptions);
+        }
+        let mut defeated = vec![];
+        for (rule_refs, _) in negations {
+            for rule_ref in rule_refs.borrow().iter() {
+                let (_, negation) = self.negation_paths(&rule_ref.rule);
+                defeated.push(negation);
+            }
+        }
+        let disabled = self.disabled.borrow();
+        for (rule_refs, matching) in response {
+            for rule_ref in rule_refs.borrow().iter().filter(|rule_ref| !disabled.contains(&rule_ref.rule.id)) {
+                let real_matching = get_real_matching(&matching, &rule_ref.varmap); 
+                let mut rule = rule_ref.rule.clone();
+                rule.support.push(key);
+                queues.match_queue.push_back(Activation::from_matching(rule, Some(real_matching), query_rules));
+            }
+        }
+        match &fact_string {
+            Some(fact_str) => info!("ADDING FACT: {}", fact_str),
+            None => info!("ADDING FACT: {}", fact),
+        }
+        self.facts.add_fact(fact_paths);
+        for negation in defeated {
+            let lost = self.justifications.retract(negation);
+            queues = self.remove_facts(lost, queues);
+        }
+        (queues, true)
+    }
+    fn process_match(&'a self,
+                     mut rule: MPRule<'a>,
+                     matching: Option<MPMatching<'a>>,
+                     mut query_rules: bool,
+                     mut queues: Queues<'a>) -> Queues<'a> {
+        if self.disabled.borrow().contains(&rule.id) {
+            return queues;
+        }
+        let old_len = rule.more_antecedents.len();
+        let (nrule, new, passed, _) = self.preprocess_matched_rule(rule, matching);
+        if !passed {
+            return queues;
+        }
+        rule = nrule;
+
+        if new {
+            if rule.more_antecedents.len() < old_len {
+                query_rules = true;
+            }
+            let mut paths: Option<Vec<MPPath>> = None;
+            let mut add_rule = true;
+            if query_rules {
+                let (new_queues, new_paths, old_rule, unique) = self.query_rule(rule, queues);
+                paths = new_paths;
+                queues = new_queues;
+                rule = old_rule;
+                add_rule = !unique;
+            }
+            if add_rule {
+                queues.rule_queue.push_back(Activation::from_rule(rule, paths, query_rules));
+            }
+        } else {
+            let strategy = self.strategy.get();
+            let times = match strategy {
+                Strategy::Recency => self.justifications.times(&rule.support),
+                _ => vec![],
+            };
+            let (salience, specificity) = (rule.meta.salience, rule.meta.specificity);
+            queues.agenda.push(strategy, salience, specificity, times, Activation::from_firing(rule, query_rules));
+        }
+        queues
+    }
+    fn fire(&'a self, rule: MPRule<'a>, query_rules: bool, mut queues: Queues<'a>) -> Queues<'a> {
+        // some fact that matched the rule may have been retracted while it waited in the agenda,
+        // or the rule may have been disabled
+        if !rule.support.iter().all(|fact| self.justifications.holds(*fact)) || self.disabled.borrow().contains(&rule.id) {
+            return queues;
+        }
+        trace!("FIRING RULE {}", rule.label());
+        *self.fired.borrow_mut().entry(rule.id).or_insert(0) += 1;
+        for consequent in rule.consequents{
+            queues.fact_queue.push_back(Activation::from_fact(consequent, Some(rule.matched.clone()), Some(rule.support.clone()), Some((rule.source, rule.meta.name)), query_rules));
+        }
+        if let Some(rule_output) = rule.output {
+            self.send_output(rule_output, rule.source, rule.meta.name, rule.matched);
+        }
+        queues
+    }
+    fn send_output(&'a self, output: &'a str, source: &str, name: Option<&str>, matched: MPMatching<'a>) {
+        let bindings = Bindings::from_matching(&matched);
+        let pre_output = self.mpparser.parse_fact(output).expect("checked when told");
+        let (paths, _, text) = self.mpparser.substitute_fact(pre_output, matched).expect("checked when matched");
+        let text = text.unwrap_or_else(|| output.to_string());
+        info!("OUTPUT: {}", text);
+        // handlers must not use the knowledge base, see `on_output`
+        let mut handlers = self.outputs.borrow_mut();
+        if handlers.is_empty() {
+            return;
+        }
+        let mut patterns = vec![];
+        if handlers.iter().any(|(_, target, _)| matches!(target, OutputTarget::Pattern(_))) {
+            let (response, _) = self.output_patterns.query_paths(paths);
+            for (rule_refs, _) in response {
+                patterns.extend(rule_refs.borrow().iter().map(|rule_ref| rule_ref.rule.source));
+            }
+        }
+        let rule = normalize_rule(source);
+        let output = Output {
+            text,
+            rule: source.trim().to_string(),
+            name: name.map(String::from),
+            bindings,
+        };
+        for (_, target, handler) in handlers.iter_mut() {
+            let selected = match target {
+                OutputTarget::All => true,
+                OutputTarget::Rule(text) => *text == rule,
+                OutputTarget::Pattern(pattern) => patterns.contains(pattern),
+            };
+            if selected {
+                handler(&output);
+            }
+        }
+    }
+    fn add_output(&'a self, target: OutputTarget<'a>, handler: OutputHandler) -> HandlerId {
+        let id = self.next_handler_id.get();
+        self.next_handler_id.set(id + 1);
+        self.outputs.borrow_mut().push((id, target, handler));
+        id
+    }
+    fn add_output_pattern(&'a self, pattern: &str, handler: OutputHandler) -> Result<HandlerId, MPError> {
+        let pattern = self.mpparser.factstr.intern(pattern.trim());
+        let paths = self.mpparser.parse_fact(pattern)?;
+        let known = self.outputs.borrow().iter().any(|(_, target, _)| matches!(target, OutputTarget::Pattern(old) if *old == pattern));
+        if !known {
+            self.add_pattern(&self.output_patterns, pattern, paths);
+        }
+        Ok(self.add_output(OutputTarget::Pattern(pattern), handler))
+    }
+    fn subscribe(&'a self, pattern: &str, subscriber: Subscriber) -> Result<(), MPError> {
+        let pattern = self.mpparser.factstr.intern(pattern.trim());
+        let paths = self.mpparser.parse_fact(pattern)?;
+        let mut subscribers = self.subscribers.borrow_mut();
+        if !subscribers.contains_key(pattern) {
+            self.add_pattern(&self.subscriptions, pattern, paths);
+        }
+        subscribers.entry(pattern).or_default().push(subscriber);
+        Ok(())
+    }
+    /// Add a pattern to a rule tree, as the only antecedent of a rule with no consequents,
+    /// to be matched against facts as the antecedents of rules are.
+    /// The pattern is the source of the rule.
+    fn add_pattern(&'a self, patterns: &'a RuleSet<'a>, pattern: &'a str, paths: Vec<MPPath<'a>>) {
+        let (varmap, normal_paths) = self.mpparser.normalize_fact(paths);
+        let rule = MPRule {
+            antecedents: Antecedents {
+                fact: Some(pattern),
+                negated: false,
+                transforms: None,
+                conditions: None,
+            },
+            more_antecedents: VecDeque::new(),
+            consequents: vec![],
+            matched: HashMap::new(),
+            output: None,
+            source: pattern,
+            support: vec![],
+            id: 0,
+            meta: Rc::new(RuleMeta::default()),
+        };
+        patterns.follow_and_create_paths(normal_paths, RuleRef { rule, varmap }, 1);
+    }
+    fn notify_subscribers(&'a self, fact: &str, subscriptions: Response<'a>) {
+        let mut subscribers = self.subscribers.borrow_mut();
+        for (rule_refs, matching) in subscriptions {
+            for rule_ref in rule_refs.borrow().iter() {
+                let bindings = Bindings::from_matching(&get_real_matching(&matching, &rule_ref.varmap));
+                for subscriber in subscribers.get_mut(rule_ref.rule.source).into_iter().flatten() {
+                    subscriber(fact, &bindings);
                 }
-                let Antecedents { fact, transforms, conditions } = antecedents;
+            }
+        }
+    }
+    fn query_rule(&'a self,
+                  mut rule: MPRule<'a>,
+                  mut queues: Queues<'a>) -> (Queues<'a>, Option<Vec<MPPath<'a>>>, MPRule<'a>, bool) {
 
-                if !transforms.is_empty() {
-                    matched = self.tparser.process_transforms(transforms, matched);
+        let mut paths: Option<Vec<MPPath>> = None;
+        let mut unique = false;
+        if rule.antecedents.fact.is_some() && !rule.antecedents.negated {
+            let MPRule {
+                antecedents,
+                more_antecedents,
+                consequents,
+                mut matched,
+                output,
+                source,
+                support,
+                id,
+                meta,
+            } = rule;
+            let fact_str = antecedents.fact.as_ref().unwrap();
+            let mut pre_ant = self.mpparser.parse_fact(fact_str).expect("checked when told");
+            if !matched.is_empty() {
+                let (new_pre_ant, old_matched, _) = self.mpparser.substitute_fact(pre_ant, matched).expect("checked when matched");
+                pre_ant = new_pre_ant;
+                matched = old_matched;
+            }
+            rule = MPRule {
+                antecedents,
+                more_antecedents,
+                consequents,
+                matched,
+                output,
+                source,
+                support,
+                id,
+                meta,
+            };
+            let (resps, old_paths, new_unique) = self.facts.ask_fact(pre_ant);
+            unique = new_unique;
+            for resp in resps {
+                let mut new_rule = rule.clone();
+                new_rule.antecedents.fact = None;
+                new_rule.support.push(self.mpparser.matched_fact_key(&old_paths, &resp));
+                queues.match_queue.push_back(Activation::from_matching(new_rule, Some(resp), true));
+            }
+            paths = Some(old_paths);
+        }
+        (queues, paths, rule, unique)
+    }
+    fn preprocess_matched_rule(&'a self,
+                               rule: MPRule<'a>,
+                               mut matching: Option<MPMatching<'a>>) -> (MPRule<'a>, bool, bool, Option<MPMatching<'a>>) {
+        let MPRule {
+            mut antecedents,
+            mut more_antecedents,
+            consequents,
+            mut matched,
+            output,
+            source,
+            support,
+            id,
+            meta,
+        } = rule;
+
+        if matching.is_some() {
+            let m = matching.unwrap();
+            matched.extend(&m);
+            matching = Some(m);
+        }
+        let Antecedents { fact, negated, transforms, conditions } = antecedents;
+
+        let mut passed = true;
+        if let Some(compiled) = &transforms {
+            if let Err(err) = self.tparser.process_transforms(compiled, &mut matched) {
+                self.add_diagnostic(source, meta.name, &matched, err);
+                passed = false;
+            }
+        }
+        if passed {
+            if let Some(compiled) = &conditions {
+                match CParser::check_conditions(compiled, &matched) {
+                    Ok(pass) => {
+                        passed = pass;
+                    },
+                    Err(err) => {
+                        self.add_diagnostic(source, meta.name, &matched, err);
+                        passed = false;
+                    },
                 }
-                if !conditions.is_empty() {
-                    let passed = CParser::check_conditions(conditions, &matched, &self.mpparser.lexicon);
-                    if !passed {
-                        return (MPRule {antecedents: Antecedents { fact, transforms, conditions }, more_antecedents, consequents, matched, output}, false, false, matching);
-                    }
+            }
+        }
+        if passed && transforms.is_some() {
+            // the values computed by the transforms must fit the grammar where they are substituted
+            let facts = more_antecedents.iter().filter_map(|ant| ant.fact)
+                .chain(consequents.iter().copied())
+                .chain(output);
+            for fact in facts {
+                let paths = self.mpparser.parse_fact(fact).expect("checked when told");
+                if let Err(err) = self.mpparser.substitute_fact(paths, matched.clone()) {
+                    self.add_diagnostic(source, meta.name, &matched, err);
+                    passed = false;
+                    break;
                 }
+            }
+        }
+        if !passed {
+            return (MPRule {antecedents: Antecedents { fact, negated, transforms, conditions }, more_antecedents, consequents, matched, output, source, support, id, meta}, false, false, matching);
+        }
 
-                if more_antecedents.len() == 0 {
-                    return (MPRule {antecedents: Antecedents { fact, transforms, conditions }, more_antecedents, consequents, matched, output}, false, true, matching);
-                } else {
-                    antecedents = more_antecedents.pop_front().unwrap();
-                }
+        if more_antecedents.is_empty() {
+            return (MPRule {antecedents: Antecedents { fact, negated, transforms, conditions }, more_antecedents, consequents, matched, output, source, support, id, meta}, false, true, matching);
+        } else {
+            antecedents = more_antecedents.pop_front().unwrap();
+        }
 
-                (MPRule {
-                    antecedents,
-                    more_antecedents,
-                    consequents,
-                    matched,
-                    output,
-                }, true, true, matching)
+        (MPRule {
+            antecedents,
+            more_antecedents,
+            consequents,
+            matched,
+            output,
+            source,
+            support,
+            id,
+            meta,
+        }, true, true, matching)
 
+    }
+    fn process_negation(&'a self, rule: MPRule<'a>, mut queues: Queues<'a>) -> Queues<'a> {
+        let (paths, negation) = self.negation_paths(&rule);
+        let (varmap, normal_paths) = self.mpparser.normalize_fact(paths.clone());
+        let support = rule.support.clone();
+        let rule_ref = RuleRef {
+            rule: rule.clone(),
+            varmap,
+        };
+        let node = self.negations.follow_and_create_paths(normal_paths, rule_ref, 1);
+        if !support.is_empty() {
+            self.justifications.derive_rule(&support, node);
+        }
+        let (exists, _) = self.facts.ask_fact_bool(paths);
+        if !exists {
+            queues = self.grant_negation(rule, negation, queues);
+        }
+        queues
+    }
+    fn grant_negation(&'a self, mut rule: MPRule<'a>, negation: FactKey, mut queues: Queues<'a>) -> Queues<'a> {
+        trace!("GRANTING NEGATION {}", rule);
+        self.justifications.tell(negation);
+        self.justifications.stamp(negation);
+        // the text is needed to prove the facts derived from the negation,
+        // whether or not provenance is being recorded
+        let fact = rule.antecedents.fact.expect("negated fact");
+        let paths = self.mpparser.parse_fact(fact).expect("checked when told");
+        let (_, _, text) = self.mpparser.substitute_fact(paths, rule.matched.clone()).expect("checked when matched");
+        let text = match &text {
+            Some(text) => self.mpparser.factstr.intern(text),
+            None => fact,
+        };
+        self.justifications.add_absent(negation, text);
+        rule.support.push(negation);
+        rule.antecedents.fact = None;
+        queues.match_queue.push_back(Activation::from_matching(rule, Some(HashMap::new()), true));
+        queues
+    }
+    fn negation_paths(&'a self, rule: &MPRule<'a>) -> (Vec<MPPath<'a>>, FactKey) {
+        let fact = rule.antecedents.fact.expect("negated fact");
+        let mut paths = self.mpparser.parse_fact(fact).expect("checked when told");
+        if !rule.matched.is_empty() {
+            let (new_paths, _, _) = self.mpparser.substitute_fact(paths, rule.matched.clone()).expect("checked when matched");
+            paths = new_paths;
+        }
+        let negation = tms::negation_key(&paths);
+        (paths, negation)
+    }
+    fn remove_facts(&'a self, lost: Vec<&'a str>, mut queues: Queues<'a>) -> Queues<'a> {
+        for fact in lost {
+            let paths = self.mpparser.parse_fact(fact).expect("facts in the knowledge base parse");
+            let (negations, paths) = self.negations.query_paths(paths);
+            let mut watching = vec![];
+            for (rule_refs, _) in negations {
+                for rule_ref in rule_refs.borrow().iter() {
+                    watching.push(rule_ref.rule.clone());
+                }
+            }
+            if self.facts.remove_fact(paths) {
+                info!("REMOVING FACT: {}", fact);
+            }
+            for rule in watching {
+                let (paths, negation) = self.negation_paths(&rule);
+                if self.justifications.is_told(negation) {
+                    continue;
+                }
+                let (exists, _) = self.facts.ask_fact_bool(paths);
+                if !exists {
+                    queues = self.grant_negation(rule, negation, queues);
+                }
             }
         }
+        queues
+    }
+    fn filter_query_matching(&'a self, antecedents: &Antecedents<'a>, matching: &mut MPMatching<'a>) -> bool {
+        if let Some(compiled) = &antecedents.transforms {
+            if let Err(err) = self.tparser.process_transforms(compiled, matching) {
+                warn!("skipping query match: {}", err);
+                return false;
+            }
+        }
+        if let Some(compiled) = &antecedents.conditions {
+            match CParser::check_conditions(compiled, matching) {
+                Ok(pass) => pass,
+                Err(err) => {
+                    warn!("skipping query match: {}", err);
+                    false
+                },
+            }
+        } else {
+            true
+        }
+    }
+    fn add_diagnostic(&'a self, rule: &str, name: Option<&str>, matching: &MPMatching<'a>, err: MPError) {
+        let diagnostic = Diagnostic::new(rule, name, matching, err);
+        warn!("{}", diagnostic);
+        self.diagnostics.borrow_mut().push(diagnostic);
+    }
+}
 
 
+/// Whether the node `to` can be reached from `from` following the edges.
+fn reaches<T>(edges: &[Vec<(usize, T)>], from: usize, to: usize) -> bool {
+    let mut seen = vec![false; edges.len()];
+    let mut stack = vec![from];
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        if !std::mem::replace(&mut seen[node], true) {
+            stack.extend(edges[node].iter().map(|(next, _)| *next));
+        }
+    }
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Grammar;
+    use crate::proof::Reason;
+
+    const GRAMMAR: &str = r#"
+        var         = @{ ("<" ~ &ASCII_ALPHA_UPPER) ~ ASCII_ALPHA_UPPER ~ ASCII_ALPHA* ~ ('0'..'9')* ~ ">" }
+        fact        = _{ SOI ~ rfact ~ EOI }
+        rfact       = { var_range ~ pred ~ var_range }
+        pred        = @{ "∈" | "⊆" }
+        v_name      = @{ ASCII_ALPHANUMERIC+ }
+        var_range   = _{ v_name | var }
+        WHITESPACE  = { (" " | "\t" | NEWLINE)+ }
+    "#;
+
+    fn new_kb() -> KnowledgeBase<Grammar> {
+        let kb = KnowledgeBase::new(Grammar::new(GRAMMAR).unwrap());
+        kb.tell("<X0> ⊆ <X1> → <X1> ⊆ <X2> → <X0> ⊆ <X2> ◊").unwrap();
+        kb.tell("<X0> ∈ <X1> → <X1> ⊆ <X2> → <X0> ∈ <X2> ◊").unwrap();
+        kb
+    }
+
+    fn ask_var(kb: &KnowledgeBase<Grammar>, query: &str, var: &str) -> Vec<String> {
+        let mut values: Vec<String> = kb.ask(query).unwrap().iter()
+            .map(|bindings| bindings.get(var).unwrap().to_string())
+            .collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn test_kb_with_runtime_grammar() {
+        let kb = new_kb();
+        kb.tell("human ⊆ primate ◊ primate ⊆ animal ◊ susan ∈ human ◊").unwrap();
+        assert_eq!(ask_var(&kb, "susan ∈ <X1> ◊", "<X1>"), vec!["animal", "human", "primate"]);
+        match kb.tell("susan is human ◊") {
+            Err(MPError::FactSyntax { .. }) => {},
+            _ => panic!("a fact out of the grammar was accepted"),
+        }
+    }
+
+    #[test]
+    fn test_retract_derived_facts() {
+        let kb = new_kb();
+        kb.tell("human ⊆ primate ◊ primate ⊆ animal ◊ mammal ⊆ animal ◊").unwrap();
+        kb.tell("susan ∈ human ◊ susan ∈ mammal ◊").unwrap();
+        kb.retract("human ⊆ primate ◊").unwrap();
+        assert_eq!(ask_var(&kb, "susan ∈ <X1> ◊", "<X1>"), vec!["animal", "human", "mammal"]);
+        kb.retract("susan ∈ mammal ◊").unwrap();
+        assert_eq!(ask_var(&kb, "susan ∈ <X1> ◊", "<X1>"), vec!["human"]);
+        assert!(kb.retract("<X1> ⊆ <X2> → <X1> ∈ <X2> ◊").is_err());
+    }
+
+    #[test]
+    fn test_negated_antecedent() {
+        let kb = new_kb();
+        kb.tell("<X1> ∈ person → ¬ <X1> ⊆ banked → <X1> ∈ unbanked ◊").unwrap();
+        kb.tell("susan ∈ person ◊ john ∈ person ◊ john ⊆ banked ◊").unwrap();
+        assert_eq!(ask_var(&kb, "<X1> ∈ unbanked ◊", "<X1>"), vec!["susan"]);
+        kb.tell("susan ⊆ banked ◊").unwrap();
+        assert!(ask_var(&kb, "<X1> ∈ unbanked ◊", "<X1>").is_empty());
+        kb.retract("john ⊆ banked ◊").unwrap();
+        assert_eq!(ask_var(&kb, "<X1> ∈ unbanked ◊", "<X1>"), vec!["john"]);
+
+        let err = kb.tell("<X1> ∈ person\n    → ¬ <X2> ⊆ banked → <X1> ∈ unbanked ◊").unwrap_err();
+        assert!(matches!(err, MPError::KnowledgeSyntax { .. }), "{}", err);
+        assert_eq!(err.position(), (2, 9));
+    }
+
+    #[test]
+    fn test_not_is_part_of_facts() {
+        let kb = KnowledgeBase::new(Grammar::new(r#"
+        var         = @{ "<" ~ ASCII_ALPHA_UPPER ~ ASCII_DIGIT* ~ ">" }
+        fact        = _{ SOI ~ rfact ~ EOI }
+        rfact       = { (v_name | var)+ }
+        v_name      = @{ ASCII_ALPHA+ }
+        WHITESPACE  = { " " }
+    "#).unwrap());
+        kb.tell("<X1> raining → not <X1> sunny → <X1> gloomy ◊").unwrap();
+        kb.tell("madrid raining ◊").unwrap();
+        assert!(kb.ask("madrid gloomy ◊").unwrap().is_empty());
+        kb.tell("not madrid sunny ◊").unwrap();
+        assert_eq!(kb.ask("madrid gloomy ◊").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unstratified_rules() {
+        let kb = KnowledgeBase::new(Grammar::new(GRAMMAR).unwrap());
+        let err = kb.tell("<X1> ∈ a → ¬ <X1> ∈ b → <X1> ∈ b ◊").unwrap_err();
+        assert!(matches!(err, MPError::KnowledgeSyntax { .. }), "{}", err);
+        kb.tell("x ∈ a ◊").unwrap();
+        assert!(kb.ask("x ∈ b ◊").unwrap().is_empty());
+
+        // the cycle may go through other rules, told before or after the negation
+        let kb = new_kb();
+        let err = kb.tell("susan ∈ person ◊\n<X1> ∈ person → ¬ <X1> ∈ banked → <X1> ∈ unbanked ◊").unwrap_err();
+        assert_eq!(err.position(), (2, 1));
+        assert_eq!(kb.rules().len(), 2);
+        assert!(kb.ask("susan ∈ person ◊").unwrap().is_empty());
+        let kb = KnowledgeBase::new(Grammar::new(GRAMMAR).unwrap());
+        kb.tell("<X1> ∈ person → ¬ <X1> ∈ banked → <X1> ∈ unbanked ◊").unwrap();
+        kb.tell("<X0> ⊆ <X1> → <X1> ⊆ <X2> → <X0> ⊆ <X2> ◊").unwrap();
+        assert!(kb.tell("<X0> ∈ <X1> → <X1> ⊆ <X2> → <X0> ∈ <X2> ◊").is_err());
+        assert_eq!(kb.rules().len(), 2);
+    }
+
+    #[test]
+    fn test_retract_unlinks_nodes() {
+        let kb = new_kb();
+        kb.tell("human ⊆ primate ◊").unwrap();
+        let before = kb.engine().facts.node_count();
+        let facts: Vec<String> = (0..50).map(|i| format!("h{} ∈ human ◊", i)).collect();
+        for fact in facts.iter() {
+            kb.tell(fact).unwrap();
+        }
+        assert!(kb.engine().facts.node_count() > before);
+        for fact in facts.iter() {
+            kb.retract(fact).unwrap();
+        }
+        assert_eq!(kb.engine().facts.node_count(), before);
+    }
+
+    #[test]
+    fn test_conjunctive_ask() {
+        let kb = new_kb();
+        kb.tell("susan ∈ person ◊ john ∈ person ◊ rex ∈ dog ◊").unwrap();
+        kb.tell("susan ∈ madrid ◊ john ∈ paris ◊ rex ∈ madrid ◊ madrid ⊆ spain ◊").unwrap();
+        let answers = kb.ask("<X1> ∈ person ∧ <X1> ∈ <X2> ∧ <X2> ⊆ spain ◊").unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].get("<X1>"), Some("susan"));
+        assert_eq!(answers[0].get("<X2>"), Some("madrid"));
+        assert_eq!(ask_var(&kb, "<X1> ∈ dog ; <X1> ∈ <X2>", "<X2>"), vec!["dog", "madrid", "spain"]);
+        assert_eq!(ask_var(&kb, "<X1> ∈ person ; <X1> ∈ paris", "<X1>"), vec!["john"]);
+        assert!(kb.ask("<X1> ∈ dog ∧ <X1> ∈ person").unwrap().is_empty());
+        assert_eq!(kb.ask("susan ∈ person ∧ susan ∈ spain").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_ask_with_transforms_and_conditions() {
+        let kb = KnowledgeBase::new(Grammar::new(GRAMMAR).unwrap());
+        kb.tell("susan ∈ person ◊ john ∈ person ◊ r2d2 ∈ person ◊").unwrap();
+        kb.tell("susan ⊆ 31 ◊ john ⊆ 25 ◊ r2d2 ⊆ old ◊").unwrap();
+
+        // the age of r2d2 is not a number, so its assignment is dropped
+        let answers = kb.ask("<X1> ∈ person ∧ <X1> ⊆ <X2> {={ <X3> n= <X2> + 1 }=}").unwrap();
+        let mut next: Vec<(&str, &str)> = answers.iter()
+            .map(|answer| (answer.get("<X1>").unwrap(), answer.get("<X3>").unwrap()))
+            .collect();
+        next.sort();
+        assert_eq!(next, vec![("john", "26"), ("susan", "32")]);
+        assert_eq!(ask_var(&kb, "<X1> ∈ person ∧ <X1> ⊆ <X2> {?{ <X2> > 30 }?}", "<X1>"), vec!["susan"]);
+        assert_eq!(ask_var(&kb, "<X1> ∈ person ; <X1> ⊆ <X2> {={ <X3> n= <X2> * 2 }=} {?{ <X3> < 60 }?}", "<X1>"), vec!["john"]);
+        assert!(kb.ask("<X1> ∈ person ∧ <X1> ⊆ <X2> {?{ <X2> > 100 }?}").unwrap().is_empty());
+        assert!(kb.ask("<X1> ∈ person {?{ <X2> > 30 }?}").is_err());
+    }
+
+    #[test]
+    fn test_transform_out_of_grammar() {
+        const NUMBERS: &str = r#"
+            var         = @{ ("<" ~ &ASCII_ALPHA_UPPER) ~ ASCII_ALPHA_UPPER ~ ASCII_ALPHA* ~ ('0'..'9')* ~ ">" }
+            fact        = _{ SOI ~ rfact ~ EOI }
+            rfact       = { var_range ~ pred ~ v_name }
+            pred        = @{ "∈" }
+            v_number    = @{ "-"? ~ ASCII_DIGIT+ }
+            v_name      = @{ ASCII_ALPHA+ }
+            var_range   = _{ v_number | var }
+            WHITESPACE  = { (" " | "\t" | NEWLINE)+ }
+        "#;
+        let kb = KnowledgeBase::new(Grammar::new(NUMBERS).unwrap());
+        kb.tell("<X1> ∈ number {={ <X2> n= <X1> / 2 }=} → <X2> ∈ half ◊").unwrap();
+        kb.tell("<X1> ∈ number {={ <X2> n= 1 / <X1> }=} → <X2> ∈ inverse ◊").unwrap();
+        kb.tell("4 ∈ number ◊").unwrap();
+        kb.tell("0 ∈ number ◊").unwrap();
+        kb.tell("1 ∈ number ◊").unwrap();
+        assert_eq!(ask_var(&kb, "<X1> ∈ half ◊", "<X1>"), vec!["0", "2"]);
+        assert_eq!(ask_var(&kb, "<X1> ∈ inverse ◊", "<X1>"), vec!["1"]);
+
+        // 1 / 4, 1 / 0 and 1 / 2 are not numbers in the grammar, and the matches are skipped
+        let diagnostics = kb.take_diagnostics();
+        let texts: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.error.text()).collect();
+        assert_eq!(texts, vec!["0.25 ∈ inverse", "inf ∈ inverse", "0.5 ∈ half"]);
+        assert!(diagnostics.iter().all(|diagnostic| matches!(diagnostic.error, MPError::FactSyntax { .. })));
+        assert_eq!(diagnostics[1].matching.get("<X1>"), Some("0"));
+    }
+
+    #[test]
+    fn test_save_and_load_snapshot() {
+        let kb = new_kb();
+        kb.tell("<X1> ∈ person → ¬ <X1> ⊆ banked → <X1> ∈ unbanked ◊").unwrap();
+        kb.tell("human ⊆ primate ◊ susan ∈ human ◊ susan ∈ person ◊").unwrap();
+        let mut snapshot = vec![];
+        kb.save(&mut snapshot).unwrap();
+
+        let loaded = KnowledgeBase::load(Grammar::new(GRAMMAR).unwrap(), snapshot.as_slice()).unwrap();
+        assert_eq!(ask_var(&loaded, "susan ∈ <X1> ◊", "<X1>"), vec!["human", "person", "primate", "unbanked"]);
+        // rules waiting for more antecedents, negations and justifications are restored
+        loaded.tell("primate ⊆ animal ◊").unwrap();
+        loaded.tell("susan ⊆ banked ◊").unwrap();
+        assert_eq!(ask_var(&loaded, "susan ∈ <X1> ◊", "<X1>"), vec!["animal", "human", "person", "primate"]);
+        loaded.retract("susan ∈ human ◊").unwrap();
+        assert_eq!(ask_var(&loaded, "susan ∈ <X1> ◊", "<X1>"), vec!["person"]);
+
+        let other = Grammar::new(&GRAMMAR.replace("\"⊆\"", "\"⊂\"")).unwrap();
+        match KnowledgeBase::load(other, snapshot.as_slice()) {
+            Err(SnapshotError::Grammar) => {},
+            _ => panic!("a snapshot was loaded with a different grammar"),
+        }
+        snapshot[8] = 99;
+        match KnowledgeBase::load(Grammar::new(GRAMMAR).unwrap(), snapshot.as_slice()) {
+            Err(SnapshotError::Version(99)) => {},
+            _ => panic!("a snapshot with an unknown version was loaded"),
+        }
+    }
+
+    #[test]
+    fn test_journal_replay_and_compact() {
+        let dir = std::env::temp_dir().join(format!("mp-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal = dir.join("kb.journal");
+        let snapshot = dir.join("kb.snapshot");
+        let open = || KnowledgeBase::open(Grammar::new(GRAMMAR).unwrap(), &journal, Some(snapshot.as_path())).unwrap();
+
+        let kb = open();
+        kb.tell("<X0> ∈ <X1> → <X1> ⊆ <X2> → <X0> ∈ <X2> ◊").unwrap();
+        kb.tell("human ⊆ primate ◊ susan ∈ human ◊ john ∈ human ◊").unwrap();
+        assert!(kb.tell("susan ∈ ◊").is_err());
+        kb.retract("john ∈ human ◊").unwrap();
+        let ids = kb.tell_with_ids("<X1> ∈ human → <X1> ∈ mortal ◊").unwrap();
+        kb.remove_rule(ids[0]).unwrap();
+        drop(kb);
+
+        let kb = open();
+        assert_eq!(ask_var(&kb, "<X1> ∈ primate ◊", "<X1>"), vec!["susan"]);
+        assert_eq!(kb.rules().len(), 1);
+        kb.compact().unwrap();
+        kb.tell("primate ⊆ animal ◊").unwrap();
+        drop(kb);
+
+        let kb = open();
+        assert_eq!(ask_var(&kb, "susan ∈ <X1> ◊", "<X1>"), vec!["animal", "human", "primate"]);
+        drop(kb);
+
+        // a record cut short by a crash is dropped
+        let mut data = fs::read(&journal).unwrap();
+        data.extend_from_slice("tell 30\nsusan ∈ ".as_bytes());
+        fs::write(&journal, &data).unwrap();
+        let kb = open();
+        assert_eq!(ask_var(&kb, "susan ∈ <X1> ◊", "<X1>"), vec!["animal", "human", "primate"]);
+        kb.tell("john ∈ animal ◊").unwrap();
+        drop(kb);
+        let kb = open();
+        assert_eq!(ask_var(&kb, "<X1> ∈ animal ◊", "<X1>"), vec!["john", "susan"]);
+        drop(kb);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_without_base() {
+        let dir = std::env::temp_dir().join(format!("mp-journal-base-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal = dir.join("kb.journal");
+        let snapshot = dir.join("kb.snapshot");
+        let open = || KnowledgeBase::open(Grammar::new(GRAMMAR).unwrap(), &journal, Some(snapshot.as_path()));
+
+        let kb = open().unwrap();
+        kb.tell("susan ∈ human ◊").unwrap();
+        let stale = fs::read(&journal).unwrap();
+        kb.compact().unwrap();
+        let fresh = fs::read(&journal).unwrap();
+        drop(kb);
+
+        // a compaction interrupted after replacing the snapshot leaves the journal with no base
+        fs::write(&journal, &stale).unwrap();
+        fs::write(dir.join("kb.journal.tmp"), &fresh).unwrap();
+        let kb = open().unwrap();
+        assert_eq!(kb.facts(), vec!["susan ∈ human"]);
+        kb.tell("john ∈ human ◊").unwrap();
+        drop(kb);
+        let kb = open().unwrap();
+        assert_eq!(kb.facts(), vec!["john ∈ human", "susan ∈ human"]);
+        drop(kb);
+
+        // otherwise, its records are not in the snapshot
+        fs::write(&journal, &stale).unwrap();
+        match open() {
+            Err(SnapshotError::Journal(_)) => {},
+            _ => panic!("a journal with no base was dropped"),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_stopped() {
+        let dir = std::env::temp_dir().join(format!("mp-journal-stopped-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal = dir.join("kb.journal");
+        let snapshot = dir.join("kb.snapshot");
+        let open = || KnowledgeBase::open(Grammar::new(GRAMMAR).unwrap(), &journal, Some(snapshot.as_path())).unwrap();
+        let budget = TellOptions { max_activations: Some(50), ..TellOptions::default() };
+
+        // counting never reaches a fixpoint, so replaying it must stop where it was stopped
+        let kb = open();
+        kb.tell("<X1> ∈ number {={ <X2> n= <X1> + 1 }=} → <X2> ∈ number ◊").unwrap();
+        assert_eq!(kb.tell_with("0 ∈ number ◊", &budget).unwrap(), Status::BudgetExhausted);
+        assert_eq!(kb.resume(&budget).unwrap(), Status::BudgetExhausted);
+        let counted = kb.ask("<X1> ∈ number ◊").unwrap().len();
+        drop(kb);
+
+        let kb = open();
+        assert_eq!(kb.ask("<X1> ∈ number ◊").unwrap().len(), counted);
+        assert!(!kb.queues().is_empty());
+        kb.compact().unwrap();
+        assert_eq!(kb.resume(&budget).unwrap(), Status::BudgetExhausted);
+        let counted = kb.ask("<X1> ∈ number ◊").unwrap().len();
+        drop(kb);
+
+        let kb = open();
+        assert_eq!(kb.ask("<X1> ∈ number ◊").unwrap().len(), counted);
+        assert_eq!(kb.resume(&budget).unwrap(), Status::BudgetExhausted);
+        assert!(kb.ask("<X1> ∈ number ◊").unwrap().len() > counted);
+        drop(kb);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_step() {
+        let dir = std::env::temp_dir().join(format!("mp-journal-step-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal = dir.join("kb.journal");
+        let open = || KnowledgeBase::open(Grammar::new(GRAMMAR).unwrap(), &journal, None).unwrap();
+        let activations = |kb: &KnowledgeBase<Grammar>| -> Vec<String> {
+            kb.queues().activations().iter().map(|activation| activation.to_string()).collect()
+        };
+
+        let kb = open();
+        kb.enqueue("<X1> ∈ human → <X1> ∈ mortal ◊ socrates ∈ human ◊ plato ∈ human ◊").unwrap();
+        for _ in 0..4 {
+            kb.step().unwrap().unwrap();
+        }
+        let (facts, waiting) = (kb.facts(), activations(&kb));
+        assert!(!waiting.is_empty());
+        drop(kb);
+
+        let kb = open();
+        assert_eq!(kb.facts(), facts);
+        assert_eq!(activations(&kb), waiting);
+        while kb.step().unwrap().is_some() {}
+        let facts = kb.facts();
+        assert!(facts.contains(&String::from("plato ∈ mortal")), "{:?}", facts);
+        drop(kb);
+
+        let kb = open();
+        assert_eq!(kb.facts(), facts);
+        assert!(kb.queues().is_empty());
+        drop(kb);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_why() {
+        let kb = new_kb();
+        kb.tell("<X1> ∈ animal → ¬ <X1> ⊆ plant → <X1> ∈ fauna ◊").unwrap();
+        kb.tell("human ⊆ primate ◊ primate ⊆ animal ◊").unwrap();
+        kb.tell("susan ∈ human ◊").unwrap();
+        let unrecorded = kb.why("susan ∈ primate").unwrap().unwrap();
+        assert_eq!(unrecorded.leaves().len(), 2);
+        match unrecorded.reason {
+            Reason::Supported { .. } => {},
+            _ => panic!("provenance recorded while off"),
+        }
+
+        kb.record_provenance(true);
+        kb.tell("john ∈ human ◊").unwrap();
+        let proof = kb.why("john ∈ fauna ◊").unwrap().unwrap();
+        match &proof.reason {
+            Reason::Derived { rule, bindings, from, .. } => {
+                assert_eq!(rule, "<X1> ∈ animal → ¬ <X1> ⊆ plant → <X1> ∈ fauna");
+                assert_eq!(bindings.get("<X1>"), Some("john"));
+                assert_eq!(from[0].fact, "john ∈ animal");
+                assert_eq!(from[1].reason, Reason::Absent);
+                assert_eq!(from[1].fact, "john ⊆ plant");
+            },
+            _ => panic!("no derivation recorded"),
+        }
+        let mut leaves: Vec<&str> = proof.leaves().iter().map(|leaf| leaf.fact.as_str()).collect();
+        leaves.sort();
+        assert_eq!(leaves, vec!["human ⊆ primate", "john ∈ human", "john ⊆ plant", "primate ⊆ animal"]);
+
+        assert_eq!(kb.why("john ⊆ plant").unwrap(), None);
+        // a negation is proven also if it was granted with provenance off, and after loading
+        kb.record_provenance(false);
+        kb.tell("mary ∈ animal ◊").unwrap();
+        let mut snapshot = vec![];
+        kb.save(&mut snapshot).unwrap();
+        let loaded = KnowledgeBase::load(Grammar::new(GRAMMAR).unwrap(), snapshot.as_slice()).unwrap();
+        for kb in [&kb, &loaded].iter() {
+            let proof = kb.why("mary ∈ fauna").unwrap().unwrap();
+            let leaves: Vec<&Proof> = proof.leaves();
+            assert_eq!(leaves[1].fact, "mary ⊆ plant");
+            assert_eq!(leaves[1].reason, Reason::Absent);
+        }
+
+        kb.retract("john ∈ human ◊").unwrap();
+        assert_eq!(kb.why("john ∈ fauna").unwrap(), None);
+        assert!(kb.why("john ∈").is_err());
+    }
+
+    #[test]
+    fn test_output_handlers() {
+        use std::rc::Rc;
+
+        let kb = new_kb();
+        let rule = "<X1> ∈ person → {<{ <X1> ∈ greeted }>} ◊";
+        kb.tell(rule).unwrap();
+        kb.tell("<X1> ∈ robot → {<{ <X1> ∈ ignored }>} ◊").unwrap();
+        let all = Rc::new(RefCell::new(vec![]));
+        let by_rule = Rc::new(RefCell::new(vec![]));
+        let by_pattern = Rc::new(RefCell::new(vec![]));
+        let sink = all.clone();
+        let id = kb.on_output(move |output| sink.borrow_mut().push(output.text.clone()));
+        let sink = by_rule.clone();
+        kb.on_rule_output("<X1> ∈ person\n    → {<{ <X1> ∈ greeted }>}", move |output| {
+            sink.borrow_mut().push(output.bindings.get("<X1>").unwrap().to_string());
+        });
+        let sink = by_pattern.clone();
+        kb.on_output_matching("<X1> ∈ ignored", move |output| sink.borrow_mut().push(output.rule.clone())).unwrap();
+        assert!(kb.on_output_matching("<X1> ∈", |_| {}).is_err());
+
+        kb.tell("susan ∈ person ◊ r2d2 ∈ robot ◊").unwrap();
+        assert_eq!(*all.borrow(), vec!["susan ∈ greeted", "r2d2 ∈ ignored"]);
+        assert_eq!(*by_rule.borrow(), vec!["susan"]);
+        assert_eq!(*by_pattern.borrow(), vec!["<X1> ∈ robot → {<{ <X1> ∈ ignored }>}"]);
+        // outputs are not facts
+        assert!(kb.ask("susan ∈ greeted ◊").unwrap().is_empty());
+
+        assert!(kb.remove_output(id));
+        assert!(!kb.remove_output(id));
+        kb.tell("john ∈ person ◊").unwrap();
+        assert_eq!(all.borrow().len(), 2);
+        assert_eq!(*by_rule.borrow(), vec!["susan", "john"]);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        use std::rc::Rc;
+
+        let kb = new_kb();
+        kb.tell("human ⊆ primate ◊ bonobo ∈ primate ◊").unwrap();
+        let seen = Rc::new(RefCell::new(vec![]));
+        let sink = seen.clone();
+        kb.subscribe("<X1> ∈ primate", move |fact, bindings| {
+            sink.borrow_mut().push(format!("{} / {}", fact, bindings.get("<X1>").unwrap()));
+        }).unwrap();
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        kb.subscribe("<X1> ∈ primate", move |_, _| counter.set(counter.get() + 1)).unwrap();
+        assert!(kb.subscribe("<X1> ∈", |_, _| {}).is_err());
+
+        kb.tell("susan ∈ human ◊ susan ∈ primate ◊ human ⊆ animal ◊").unwrap();
+        kb.tell("chita ∈ primate ◊").unwrap();
+        assert_eq!(*seen.borrow(), vec!["susan ∈ primate / susan", "chita ∈ primate / chita"]);
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        let kb = new_kb();
+        let ids = kb.tell_with_ids("
+            <X1> ∈ person → <X1> ∈ <X2> → <X2> ⊆ visited ◊
+            <X1> ∈ mammal → <X1> ∈ animal ◊
+        ").unwrap();
+        assert_eq!(ids, vec![3, 4]);
+        kb.tell("susan ∈ person ◊").unwrap();
+        let rules = kb.rules();
+        assert_eq!(rules.iter().map(|rule| rule.id).collect::<Vec<RuleId>>(), vec![1, 2, 3, 4]);
+        assert_eq!(rules[2].source, "<X1> ∈ person → <X1> ∈ <X2> → <X2> ⊆ visited");
+        assert_eq!(rules[2].partial.len(), 1);
+        assert_eq!(rules[2].partial[0].remaining, 1);
+        assert_eq!(rules[2].partial[0].bindings.get("<X1>"), Some("susan"));
+
+        let mut snapshot = vec![];
+        kb.save(&mut snapshot).unwrap();
+        let loaded = KnowledgeBase::load(Grammar::new(GRAMMAR).unwrap(), snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.rules(), rules);
+        assert_eq!(loaded.tell_with_ids("<X1> ∈ plant → <X1> ∈ flora ◊").unwrap(), vec![5]);
+
+        // the partially matched rule goes along with the rule
+        assert!(kb.remove_rule(3).unwrap());
+        assert!(!kb.remove_rule(3).unwrap());
+        assert_eq!(kb.rules().iter().map(|rule| rule.id).collect::<Vec<RuleId>>(), vec![1, 2, 4]);
+        kb.tell("susan ∈ home ◊").unwrap();
+        assert!(kb.ask("home ⊆ visited ◊").unwrap().is_empty());
+        loaded.tell("susan ∈ home ◊").unwrap();
+        assert_eq!(loaded.ask("home ⊆ visited ◊").unwrap().len(), 1);
+
+        // and so does an activation waiting in the agenda
+        kb.enqueue("jane ∈ mammal ◊").unwrap();
+        while kb.queues().agenda().is_empty() {
+            kb.step().unwrap().unwrap();
+        }
+        assert!(kb.remove_rule(4).unwrap());
+        assert_eq!(kb.run().unwrap(), Status::Fixpoint);
+        assert!(kb.ask("jane ∈ animal ◊").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_annotated_rules() {
+        let kb = new_kb();
+        kb.record_provenance(true);
+        kb.tell("
+            @name(stock-alert) @tag(finance) <X1> ∈ stock → <X1> ∈ alerted ◊
+            @tag(finance) <X1> ∈ bond → <X1> ∈ alerted ◊
+        ").unwrap();
+        let rules = kb.rules();
+        assert_eq!(rules[2].name.as_deref(), Some("stock-alert"));
+        assert_eq!(rules[2].tags, vec!["finance"]);
+        assert_eq!(rules[3].name, None);
+
+        assert_eq!(kb.disable_rules("@tag(finance)").unwrap(), vec![3, 4]);
+        kb.tell("acme ∈ stock ◊ treasury ∈ bond ◊").unwrap();
+        assert!(kb.ask("<X1> ∈ alerted ◊").unwrap().is_empty());
+        assert_eq!(kb.enable_rules("@name(stock-alert)").unwrap(), vec![3]);
+        kb.tell("ibm ∈ stock ◊ bund ∈ bond ◊").unwrap();
+        assert_eq!(ask_var(&kb, "<X1> ∈ alerted ◊", "<X1>"), vec!["ibm"]);
+        let rules = kb.rules();
+        assert!(rules[2].enabled && !rules[3].enabled);
+        assert_eq!(rules[2].fired, 1);
+        match kb.why("ibm ∈ alerted").unwrap().unwrap().reason {
+            Reason::Derived { name, .. } => assert_eq!(name.as_deref(), Some("stock-alert")),
+            _ => panic!("no derivation recorded"),
+        }
+
+        assert_eq!(kb.remove_rules("@tag(finance)").unwrap(), vec![3, 4]);
+        assert_eq!(kb.rules().len(), 2);
+        assert!(kb.tell("@name(a) @name(b) <X1> ∈ stock → <X1> ∈ alerted ◊").is_err());
+        assert!(kb.tell("@owner(me) <X1> ∈ stock → <X1> ∈ alerted ◊").is_err());
+        assert!(kb.disable_rules("finance").is_err());
+
+        // activations waiting in the agenda do not fire a rule disabled after they were added
+        kb.tell("@name(late) <X1> ∈ stock → <X1> ∈ late ◊").unwrap();
+        kb.enqueue("sap ∈ stock ◊").unwrap();
+        while kb.queues().agenda().is_empty() {
+            kb.step().unwrap().unwrap();
+        }
+        let ids = kb.disable_rules("@name(late)").unwrap();
+        assert_eq!(kb.run().unwrap(), Status::Fixpoint);
+        assert!(kb.ask("sap ∈ late ◊").unwrap().is_empty());
+        kb.enable_rules("@name(late)").unwrap();
+        assert!(kb.ask("sap ∈ late ◊").unwrap().is_empty());
+        assert_eq!(kb.rules().iter().find(|rule| rule.id == ids[0]).unwrap().fired, 0);
+    }
+
+    #[test]
+    fn test_agenda() {
+        use std::rc::Rc;
+
+        fn fired(kb: &KnowledgeBase<Grammar>) -> Rc<RefCell<Vec<String>>> {
+            let fired = Rc::new(RefCell::new(vec![]));
+            let sink = fired.clone();
+            kb.on_output(move |output| sink.borrow_mut().push(output.text.clone()));
+            fired
+        }
+        let kb = new_kb();
+        kb.tell("
+            @salience(-10) <X1> ∈ person → {<{ <X1> ∈ default }>} ◊
+            <X1> ∈ person → {<{ <X1> ∈ plain }>} ◊
+            @salience(10) <X1> ∈ person → {<{ <X1> ∈ override }>} ◊
+        ").unwrap();
+        let outputs = fired(&kb);
+        kb.tell("susan ∈ person ◊").unwrap();
+        assert_eq!(*outputs.borrow(), vec!["susan ∈ override", "susan ∈ plain", "susan ∈ default"]);
+        assert_eq!(kb.rules()[2].salience, -10);
+        assert_eq!(kb.disable_rules("@salience(10)").unwrap(), vec![5]);
+        assert!(kb.tell("@salience(high) <X1> ∈ person → <X1> ∈ human ◊").is_err());
+        assert!(kb.tell("@salience(1) @salience(2) <X1> ∈ person → <X1> ∈ human ◊").is_err());
+
+        // by default, rules fire in the order in which they were activated, as they always did
+        assert_eq!(new_kb().strategy(), Strategy::Breadth);
+
+        // the rule with two antecedents is activated last, but it is more specific,
+        // and it is also matched with more facts
+        for (strategy, first) in [
+            (Strategy::Depth, "specific"),
+            (Strategy::Breadth, "plain"),
+            (Strategy::Recency, "specific"),
+            (Strategy::Specificity, "specific"),
+        ].iter() {
+            let kb = new_kb();
+            kb.set_strategy(*strategy);
+            kb.tell("
+                <X1> ∈ person → {<{ <X1> ∈ plain }>} ◊
+                <X1> ∈ person → <X1> ∈ human → {<{ <X1> ∈ specific }>} ◊
+            ").unwrap();
+            let outputs = fired(&kb);
+            kb.tell("susan ∈ human ◊").unwrap();
+            kb.tell("susan ∈ person ◊").unwrap();
+            assert_eq!(outputs.borrow()[0], format!("susan ∈ {}", first), "{:?}", strategy);
+            assert_eq!(outputs.borrow().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_tell_with_limits() {
+        use std::time::Instant;
+        use crate::inference::CancelToken;
+
+        let kb = new_kb();
+        let budget = TellOptions { max_activations: Some(100), ..TellOptions::default() };
+        assert_eq!(kb.tell_with("a ⊆ b ◊ b ⊆ c ◊ c ⊆ d ◊", &TellOptions { max_activations: Some(1), ..TellOptions::default() }).unwrap(), Status::BudgetExhausted);
+        // the activations left are kept in snapshots
+        let mut snapshot = vec![];
+        kb.save(&mut snapshot).unwrap();
+        let loaded = KnowledgeBase::load(Grammar::new(GRAMMAR).unwrap(), snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.resume(&budget).unwrap(), Status::Fixpoint);
+        assert_eq!(ask_var(&loaded, "a ⊆ <X1> ◊", "<X1>"), vec!["b", "c", "d"]);
+        assert_eq!(kb.resume(&budget).unwrap(), Status::Fixpoint);
+        assert_eq!(ask_var(&kb, "a ⊆ <X1> ◊", "<X1>"), vec!["b", "c", "d"]);
+
+        // counting never reaches a fixpoint
+        kb.tell("<X1> ∈ number {={ <X2> n= <X1> + 1 }=} → <X2> ∈ number ◊").unwrap();
+        assert_eq!(kb.tell_with("0 ∈ number ◊", &budget).unwrap(), Status::BudgetExhausted);
+        let counted = kb.ask("<X1> ∈ number ◊").unwrap().len();
+        assert!(counted > 1);
+        let mut snapshot = vec![];
+        kb.save(&mut snapshot).unwrap();
+        let loaded = KnowledgeBase::load(Grammar::new(GRAMMAR).unwrap(), snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.ask("<X1> ∈ number ◊").unwrap().len(), counted);
+        assert_eq!(loaded.resume(&budget).unwrap(), Status::BudgetExhausted);
+        assert!(loaded.ask("<X1> ∈ number ◊").unwrap().len() > counted);
+        assert_eq!(kb.resume(&budget).unwrap(), Status::BudgetExhausted);
+        assert!(kb.ask("<X1> ∈ number ◊").unwrap().len() > counted);
+
+        let cancel = CancelToken::new();
+        let options = TellOptions { cancel: Some(cancel.clone()), ..budget.clone() };
+        assert_eq!(kb.resume(&options).unwrap(), Status::BudgetExhausted);
+        cancel.cancel();
+        let counted = kb.ask("<X1> ∈ number ◊").unwrap().len();
+        assert_eq!(kb.tell_with("a ⊆ e ◊", &options).unwrap(), Status::Cancelled);
+        assert_eq!(kb.ask("<X1> ∈ number ◊").unwrap().len(), counted);
+        let options = TellOptions { deadline: Some(Instant::now()), ..TellOptions::default() };
+        assert_eq!(kb.resume(&options).unwrap(), Status::DeadlineReached);
+        // the fact told while cancelled is still pending
+        assert!(kb.ask("a ⊆ e ◊").unwrap().is_empty());
+        kb.resume(&budget).unwrap();
+        assert_eq!(kb.ask("a ⊆ e ◊").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_step() {
+        let kb = new_kb();
+        kb.enqueue("<X1> ∈ human → <X1> ∈ mortal ◊ socrates ∈ human ◊").unwrap();
+        {
+            let queues = kb.queues();
+            assert_eq!(queues.rule_queue().len(), 1);
+            let activations: Vec<String> = queues.activations().iter().map(|act| act.to_string()).collect();
+            assert_eq!(activations, vec!["add rule #3 (antecedents left: 1)", "add fact socrates ∈ human"]);
+        }
+        assert!(kb.ask("socrates ∈ human ◊").unwrap().is_empty());
+        let step = kb.step().unwrap().unwrap();
+        assert_eq!(step.activation, "add rule #3 (antecedents left: 1)");
+        assert!(step.enqueued.is_empty());
+        let step = kb.step().unwrap().unwrap();
+        assert_eq!(step.activation, "add fact socrates ∈ human");
+        assert!(step.enqueued.contains(&String::from("match rule #3 with {<X1>: socrates}")), "{:?}", step.enqueued);
+        let mut steps = vec![];
+        while let Some(step) = kb.step().unwrap() {
+            steps.push(step);
+        }
+        assert!(steps.iter().any(|step| step.activation == "fire rule #3 with {<X1>: socrates}"));
+        assert_eq!(kb.ask("socrates ∈ mortal ◊").unwrap().len(), 1);
+        assert!(kb.queues().is_empty());
+
+        kb.enqueue("plato ∈ human ◊").unwrap();
+        assert_eq!(kb.run().unwrap(), Status::Fixpoint);
+        assert_eq!(ask_var(&kb, "<X1> ∈ mortal ◊", "<X1>"), vec!["plato", "socrates"]);
+    }
+
+    #[test]
+    fn test_load_facts() {
+        let kb = new_kb();
+        kb.tell("<X1> ∈ human → <X1> ∈ mortal ◊").unwrap();
+        let humans: Vec<String> = (0..12_000).map(|i| format!("h{} ∈ human ◊", i % 10_500)).collect();
+        let loaded = kb.load_facts(humans.iter()).unwrap();
+        assert_eq!(loaded, BulkLoad { added: 10_500, duplicates: 1_500 });
+        assert_eq!(kb.ask("<X1> ∈ mortal ◊").unwrap().len(), 10_500);
+        assert!(kb.queues().is_empty());
+
+        let input = "socrates ∈ human ◊\n/* not yet */ plato ∈ human <>\nh1 ∈ human ◊\naristotle ∈ human";
+        let loaded = kb.load_facts_from(input.as_bytes()).unwrap();
+        assert_eq!(loaded, BulkLoad { added: 3, duplicates: 1 });
+        assert_eq!(kb.ask("aristotle ∈ mortal ◊").unwrap().len(), 1);
+
+        match kb.load_facts_from("zeno ∈ human ◊\n  zeno ∈ ∈ ◊".as_bytes()) {
+            Err(err @ MPError::FactSyntax { .. }) => assert_eq!(err.position().0, 2),
+            other => panic!("expected a fact syntax error, got {:?}", other),
+        }
+        assert_eq!(kb.ask("zeno ∈ mortal ◊").unwrap().len(), 0);
+        match kb.load_facts(vec!["zeno ∈ human", "zeno ∈ ∈", "zeno ∈ greek"]) {
+            Err(err @ MPError::FactSyntax { .. }) => assert_eq!(err.position().0, 2),
+            other => panic!("expected a fact syntax error, got {:?}", other),
+        }
+        // only facts can be loaded in bulk
+        assert!(kb.load_facts(vec!["<X1> ∈ human → <X1> ∈ animal"]).is_err());
+    }
+
+    #[test]
+    fn test_tell_file() {
+        let dir = std::env::temp_dir().join(format!("mp-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("rules")).unwrap();
+        fs::write(dir.join("main.mp"), "@include \"rules/mortal.mp\" ◊\n\nsocrates ∈ human ◊\n").unwrap();
+        fs::write(dir.join("rules/mortal.mp"), "/* humans die */\n<X1> ∈ human\n    → <X1> ∈ mortal ◊\n").unwrap();
+        let kb = new_kb();
+        kb.tell_file(dir.join("main.mp")).unwrap();
+        assert_eq!(kb.ask("socrates ∈ mortal ◊").unwrap().len(), 1);
+
+        kb.tell_reader("plato ∈ human ◊ plato ∈ philosopher ◊".as_bytes()).unwrap();
+        assert_eq!(ask_var(&kb, "<X1> ∈ mortal ◊", "<X1>"), vec!["plato", "socrates"]);
+        assert!(kb.tell("@include \"main.mp\" ◊").is_err());
+
+        fs::write(dir.join("bad.mp"), "zeno ∈ human ◊\n\n  zeno ∈ ∈ ◊\n").unwrap();
+        fs::write(dir.join("outer.mp"), "\n@include \"bad.mp\" ◊\n").unwrap();
+        match kb.tell_file(dir.join("outer.mp")) {
+            Err(MPError::InFile { file, error }) => {
+                assert_eq!(file, dir.join("bad.mp"));
+                assert_eq!(error.position(), (3, 10));
+            },
+            other => panic!("expected an error in bad.mp, got {:?}", other),
+        }
+        fs::write(dir.join("bad_rule.mp"), "a ∈ b ◊\n<X1> ∈ human\n  → <X1> ∈ ∈ ◊\n").unwrap();
+        let err = kb.tell_file(dir.join("bad_rule.mp")).unwrap_err();
+        assert!(matches!(&err, MPError::InFile { error, .. } if matches!(**error, MPError::FactSyntax { .. })), "{}", err);
+        assert_eq!(err.position(), (3, 12));
+        fs::write(dir.join("a.mp"), "@include \"b.mp\" ◊").unwrap();
+        fs::write(dir.join("b.mp"), "a ∈ b ◊\n@include \"a.mp\" ◊").unwrap();
+        let err = kb.tell_file(dir.join("a.mp")).unwrap_err();
+        assert!(err.message().starts_with("include cycle"), "{}", err);
+        assert_eq!(err.position(), (2, 1));
+        assert!(kb.tell_file(dir.join("missing.mp")).is_err());
+        fs::remove_dir_all(dir).unwrap();
     }
 }
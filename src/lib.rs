@@ -44,15 +44,16 @@
 
 
 pub mod activation;
+pub mod arena;
 pub mod constants;
 pub mod segment;
 pub mod matching;
 pub mod path;
 //pub mod fact;
-mod parser;
+pub mod parser;
 pub mod facttree;
 pub mod ruletree;
-mod knowledge;
+pub mod knowledge;
 pub mod lexicon;
 pub mod kbase;
 pub mod kparser;
@@ -60,9 +61,22 @@ pub mod transform;
 pub mod transform_num;
 pub mod transform_str;
 pub mod condition;
+pub mod error;
+pub mod bindings;
+pub mod tms;
+pub mod grammar;
+pub mod snapshot;
+pub mod journal;
+pub mod proof;
+pub mod output;
+pub mod agenda;
+pub mod inference;
+pub mod reader;
+pub mod repl;
 
 
 extern crate pest;
+extern crate pest_meta;
 #[macro_use]
 extern crate pest_derive;
 
@@ -79,43 +93,43 @@ pub fn derive_kbase(input: proc_macro::TokenStream) -> TokenStream {
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
     let name = ast.ident;
     let attr = &ast.attrs[0];
-
-    let derived_parser = parser::derive_parser(attr);
-    let derived_kb = knowledge::derive_kb();
+    let source = match attr.parse_meta() {
+        Ok(syn::Meta::NameValue(syn::MetaNameValue { path, lit: syn::Lit::Str(lit), .. })) => {
+            if path.is_ident("grammar_inline") {
+                quote! { #lit }
+            } else {
+                // pest_derive looks for grammar files in the src directory of the crate
+                let file = format!("/src/{}", lit.value());
+                quote! { include_str!(concat!(env!("CARGO_MANIFEST_DIR"), #file)) }
+            }
+        },
+        _ => panic!("expected #[grammar = \"...\"] or #[grammar_inline = \"...\"]"),
+    };
 
     quote! {
 
-        use std::collections::{ HashMap, HashSet, VecDeque };
-        use std::cell::RefCell;
-        use std::mem;
-
-        use log::{info, trace};
-
-        use pest::error::Error;
-        use pest::Parser;
-        use pest::iterators::Pair;
-        use modus_ponens::constants;
-        use modus_ponens::activation::{ ParseResult, Activation };
-        use modus_ponens::facttree::FactSet;
-        use modus_ponens::kbase::{ KBase, KBGen };
-        use modus_ponens::lexicon::Lexicon;
-        use modus_ponens::matching::{ MPMatching, get_real_matching};
-        use modus_ponens::path::MPPath;
-        use modus_ponens::ruletree::{ Antecedents, MPRule, RuleSet, RuleRef };
-        use modus_ponens::segment::MPSegment;
-        use modus_ponens::kparser;
-        use modus_ponens::transform::TParser;
-        use modus_ponens::condition::CParser;
-
-
-        #derived_parser
-
-        #derived_kb
-
-        impl<'a> KBGen<'a> for #name {
-            type Output = KB<'a>;
-            fn gen_kb() -> KB<'a> {
-                KB::new()
+        #[derive(Parser)]
+        #attr
+        pub struct FactParser;
+
+        impl modus_ponens::grammar::FactGrammar for FactParser {
+            type Rule<'g> = Rule;
+
+            fn parse_fact<'g, 'i>(&'g self, text: &'i str) -> Result<pest::iterators::Pairs<'i, Rule>, Box<pest::error::Error<Rule>>> {
+                <FactParser as pest::Parser<Rule>>::parse(Rule::fact, text).map_err(Box::new)
+            }
+
+            fn source(&self) -> &str {
+                #source
+            }
+        }
+
+        pub type KB = modus_ponens::knowledge::KnowledgeBase<FactParser>;
+
+        impl modus_ponens::kbase::KBGen for #name {
+            type Output = KB;
+            fn gen_kb() -> KB {
+                modus_ponens::knowledge::KnowledgeBase::new(FactParser)
             }
         }
     }
@@ -0,0 +1,65 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! Handlers for the output sections of rules, `{<{ ... }>}`.
+
+use crate::bindings::Bindings;
+
+
+/// An output produced by a rule, once its antecedents were matched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// The text of the output section, with the variables substituted.
+    pub text: String,
+    /// The text of the rule, as it was told.
+    pub rule: String,
+    /// The name of the rule, if it was given one with `@name(...)`.
+    pub name: Option<String>,
+    /// The variable assignments of the rule.
+    pub bindings: Bindings,
+}
+
+/// A callback that receives outputs.
+pub type OutputHandler = Box<dyn FnMut(&Output)>;
+
+/// Identifies a registered output handler, to remove it.
+pub type HandlerId = u64;
+
+/// The outputs that a handler receives.
+pub enum OutputTarget<'a> {
+    All,
+    /// Outputs of the rule with this text, with normalized whitespace.
+    Rule(String),
+    /// Outputs that match this fact pattern.
+    Pattern(&'a str),
+}
+
+impl<'a> OutputTarget<'a> {
+    pub fn rule(text: &str) -> OutputTarget<'a> {
+        OutputTarget::Rule(normalize_rule(text))
+    }
+}
+
+/// The text of a rule, without the final `◊` and with normalized whitespace,
+/// to identify rules regardless of their layout.
+pub fn normalize_rule(text: &str) -> String {
+    let text = text.trim();
+    let text = text.strip_suffix('◊').unwrap_or(text);
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
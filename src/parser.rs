@@ -16,218 +16,470 @@
 // You should have received a copy of the GNU General Public License    
 // along with any part of the modus_ponens project.    
 // If not, see <http://www.gnu.org/licenses/>.
-//
 
-extern crate proc_macro;
-extern crate proc_macro2;
-extern crate syn;
+use std::collections::{ HashMap, HashSet, VecDeque };
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::mem;
 
-use proc_macro2::TokenStream;
+use pest::{ Parser, RuleType };
+use pest::iterators::Pair;
 
+use crate::constants;
+use crate::activation::ParseResult;
+use crate::lexicon::Lexicon;
+use crate::matching::MPMatching;
+use crate::path::MPPath;
+use crate::ruletree::{ Antecedents, MPRule, RuleMeta };
+use crate::segment::MPSegment;
+use crate::kparser;
+use crate::transform::TParser;
+use crate::condition::CParser;
+use crate::error::{ MPError, line_col };
+use crate::grammar::FactGrammar;
+use crate::tms::{ self, FactKey };
 
-pub fn derive_parser(attr: &syn::Attribute) -> TokenStream {
-    quote! {
 
-        pub struct StringCache(RefCell<HashSet<String>>);
+pub struct StringCache(RefCell<HashSet<String>>);
 
-        impl StringCache {
-            fn new() -> Self {
-                StringCache(RefCell::new(HashSet::new()))
-            }
+impl StringCache {
+    fn new() -> Self {
+        StringCache(RefCell::new(HashSet::new()))
+    }
 
-            fn intern<'a>(&'a self, s: &str) -> &'a str {
-                let mut set = self.0.borrow_mut();
-                let mut interned = set.get(s);
+    pub fn intern<'a>(&'a self, s: &str) -> &'a str {
+        let mut set = self.0.borrow_mut();
+        let mut interned = set.get(s);
 
-                if interned.is_none() {
-                    set.insert(s.into());
-                    interned = set.get(s);
-                }
-                unsafe { mem::transmute(interned.unwrap().as_str()) }
-            }
-        }
-
-        pub struct MPParser<'a> {
-            pub lexicon: Box<Lexicon>,
-            pub flexicon: StringCache,
-            pub math: StringCache,
-            pub factstr: StringCache,
-            dummy: &'a str,
+        if interned.is_none() {
+            set.insert(s.into());
+            interned = set.get(s);
         }
+        unsafe { mem::transmute(interned.unwrap().as_str()) }
+    }
+}
 
-        #[derive(Parser)]
-        #attr
-        pub struct FactParser;
+pub struct MPParser<'a, P: FactGrammar> {
+    pub grammar: P,
+    pub lexicon: Box<Lexicon>,
+    pub flexicon: StringCache,
+    pub math: StringCache,
+    pub factstr: StringCache,
+    dummy: &'a str,
+}
 
-        impl<'a> MPParser<'a> {
+impl<'a, P: FactGrammar> MPParser<'a, P> {
 
-            pub fn new() -> MPParser<'a> {
-                MPParser {
-                    lexicon: Box::new(Lexicon::new()),
-                    flexicon: StringCache::new(),
-                    math: StringCache::new(),
-                    factstr: StringCache::new(),
-                    dummy: "",
-                }
-            }
+    pub fn new(grammar: P) -> MPParser<'a, P> {
+        MPParser {
+            grammar,
+            lexicon: Box::new(Lexicon::new()),
+            flexicon: StringCache::new(),
+            math: StringCache::new(),
+            factstr: StringCache::new(),
+            dummy: "",
+        }
+    }
 
-            pub fn parse_text(&'a self, text: &'a str) -> Result<ParseResult<'a>, Error<kparser::Rule>> {
-                let parse_tree = kparser::KParser::parse(kparser::Rule::knowledge, text)?.next().expect("initial parse tree");
-                let mut facts: Vec<&'a str> = vec![];
-                let mut rules: Vec<MPRule> = vec![];
-                for pair in parse_tree.into_inner() {
-                    match pair.as_rule() {
-                        kparser::Rule::fact => {
-                            facts.push(pair.as_str());
-                        },
-                        kparser::Rule::rule => {
-                            let mut more_antecedents = VecDeque::new();
-                            let mut consequents = vec![];
-                            let mut output: Option<&str> = None;
-                            for pairset in pair.into_inner() {
-                                match pairset.as_rule() {
-                                    kparser::Rule::antecedents => {
-                                        let mut ant = "";
-                                        let mut transforms = "";
-                                        let mut conditions = "";
-                                        for factpair in pairset.into_inner() {
-                                            match factpair.as_rule() {
-                                                kparser::Rule::fact => {
-                                                    ant = self.factstr.intern(factpair.as_str());
-                                                },
-                                                kparser::Rule::transforms => {
-                                                    transforms = self.math.intern(factpair.as_str());
-                                                },
-                                                kparser::Rule::conditions => {
-                                                    conditions = self.math.intern(factpair.as_str());
-                                                },
-                                                _ => {}
-                                            }
-                                        }
-                                        more_antecedents.push_back(Antecedents {
-                                            fact: Some(ant),
-                                            transforms,
-                                            conditions,
-                                        });
-                                    },
-                                    kparser::Rule::consequents => {
-                                        for factpair in pairset.into_inner() {
-                                            match factpair.as_rule() {
-                                                kparser::Rule::fact => {
-                                                    consequents.push(self.factstr.intern(factpair.as_str()));
-                                                },
-                                                kparser::Rule::output => {
-                                                    output = Some(self.factstr.intern(factpair.as_str()));
-                                                },
-                                                _ => {}
-                                            }
-                                        }
+    pub fn parse_text<'t>(&'a self, text: &'t str) -> Result<ParseResult<'a>, MPError> {
+        let parse_tree = match kparser::KParser::parse(kparser::Rule::knowledge, text) {
+            Ok(mut pairs) => pairs.next().expect("initial parse tree"),
+            Err(err) => return Err(MPError::knowledge(text, err)),
+        };
+        let mut facts: Vec<&'a str> = vec![];
+        let mut rules: Vec<MPRule> = vec![];
+        for pair in parse_tree.into_inner() {
+            match pair.as_rule() {
+                kparser::Rule::fact => {
+                    let offset = pair.as_span().start();
+                    self.check_fact(pair.as_str()).map_err(|err| err.within(text, offset))?;
+                    facts.push(self.factstr.intern(pair.as_str()));
+                },
+                kparser::Rule::include => {
+                    let (line, col) = line_col(text, pair.as_span().start());
+                    return Err(MPError::KnowledgeSyntax {
+                        text: text.to_string(),
+                        line,
+                        col,
+                        message: String::from("@include can only be used in knowledge read from files or readers"),
+                    });
+                },
+                kparser::Rule::rule => {
+                    let source = self.factstr.intern(pair.as_str());
+                    let rule_start = pair.as_span().start();
+                    let mut bound: HashSet<String> = HashSet::new();
+                    let mut more_antecedents = VecDeque::new();
+                    let mut consequents = vec![];
+                    let mut output: Option<&str> = None;
+                    let mut meta = RuleMeta::default();
+                    let mut salience = None;
+                    for pairset in pair.into_inner() {
+                        match pairset.as_rule() {
+                            kparser::Rule::annotation => {
+                                let offset = pairset.as_span().start() - rule_start;
+                                let (key, value) = self.annotation(pairset).map_err(|err| err.within(source, offset))?;
+                                let repeated = match key.as_str() {
+                                    "tag" => {
+                                        meta.tags.push(self.factstr.intern(&value));
+                                        false
                                     },
-                                    _ => {}
+                                    "salience" => salience.replace(value).is_some(),
+                                    _ => meta.name.replace(self.factstr.intern(&value)).is_some(),
+                                };
+                                if repeated {
+                                    let (line, col) = line_col(source, offset);
+                                    return Err(MPError::KnowledgeSyntax {
+                                        text: source.to_string(),
+                                        line,
+                                        col,
+                                        message: format!("a rule can only have one {}", key),
+                                    });
                                 }
-                            }
-                            let antecedents = more_antecedents.pop_front().unwrap();
-                            let rule = MPRule {
-                                antecedents,
-                                more_antecedents,
-                                consequents,
-                                matched: HashMap::new(),
-                                output,
-                            };
-                            rules.push(rule);
-                        },
-                        _ => {}
+                            },
+                            kparser::Rule::antecedents => {
+                                let mut ant = "";
+                                let mut negated = false;
+                                let mut transforms = None;
+                                let mut conditions = None;
+                                for factpair in pairset.into_inner() {
+                                    match factpair.as_rule() {
+                                        kparser::Rule::fact => {
+                                            let offset = factpair.as_span().start() - rule_start;
+                                            self.bind_fact_vars(factpair.as_str(), &mut bound)
+                                                .map_err(|err| err.within(source, offset))?;
+                                            ant = self.factstr.intern(factpair.as_str());
+                                        },
+                                        kparser::Rule::negated_fact => {
+                                            let negpair = factpair.into_inner()
+                                                .find(|pair| pair.as_rule() == kparser::Rule::fact)
+                                                .expect("negated fact");
+                                            let offset = negpair.as_span().start() - rule_start;
+                                            self.check_negation_vars(negpair.as_str(), &bound).map_err(|err| err.within(source, offset))?;
+                                            ant = self.factstr.intern(negpair.as_str());
+                                            negated = true;
+                                        },
+                                        kparser::Rule::transforms => {
+                                            let offset = factpair.as_span().start() - rule_start;
+                                            let text = self.math.intern(factpair.as_str());
+                                            let compiled = TParser::compile(text, &self.lexicon, &mut bound)
+                                                .map_err(|err| err.within(source, offset))?;
+                                            transforms = Some(Rc::new(compiled));
+                                        },
+                                        kparser::Rule::conditions => {
+                                            let offset = factpair.as_span().start() - rule_start;
+                                            let text = self.math.intern(factpair.as_str());
+                                            let compiled = CParser::compile(text, &self.lexicon, &bound)
+                                                .map_err(|err| err.within(source, offset))?;
+                                            conditions = Some(Rc::new(compiled));
+                                        },
+                                        _ => {}
+                                    }
+                                }
+                                more_antecedents.push_back(Antecedents {
+                                    fact: Some(ant),
+                                    negated,
+                                    transforms,
+                                    conditions,
+                                });
+                            },
+                            kparser::Rule::consequents => {
+                                for factpair in pairset.into_inner() {
+                                    match factpair.as_rule() {
+                                        kparser::Rule::fact => {
+                                            let offset = factpair.as_span().start() - rule_start;
+                                            self.check_fact(factpair.as_str()).map_err(|err| err.within(source, offset))?;
+                                            consequents.push(self.factstr.intern(factpair.as_str()));
+                                        },
+                                        kparser::Rule::output => {
+                                            let offset = factpair.as_span().start() - rule_start;
+                                            self.check_fact(factpair.as_str()).map_err(|err| err.within(source, offset))?;
+                                            output = Some(self.factstr.intern(factpair.as_str()));
+                                        },
+                                        _ => {}
+                                    }
+                                }
+                            },
+                            _ => {}
+                        }
                     }
-                }
-                Ok(ParseResult { facts, rules })
+                    if let Some(value) = salience {
+                        meta.salience = value.parse().expect("checked with the annotation");
+                    }
+                    meta.specificity = more_antecedents.iter()
+                        .map(|ant| 1 + ant.conditions.as_ref().map_or(0, |compiled| compiled.conditions.len()))
+                        .sum();
+                    let antecedents = more_antecedents.pop_front().unwrap();
+                    let rule = MPRule {
+                        antecedents,
+                        more_antecedents,
+                        consequents,
+                        matched: HashMap::new(),
+                        output,
+                        source,
+                        support: vec![],
+                        id: 0,
+                        meta: Rc::new(meta),
+                    };
+                    rules.push(rule);
+                },
+                _ => {}
             }
+        }
+        Ok(ParseResult { facts, rules })
+    }
 
-            pub fn parse_fact(&'a self, text: &'a str) -> Vec<MPPath<'a>> {
-                let parse_tree = FactParser::parse(Rule::fact, text).ok().expect("fact pairset").next().expect("fact pair");
-                self.visit_parse_node(parse_tree,
-                                      vec![],
-                                      vec![],
-                                      0)
+    pub fn parse_query<'t>(&'a self, text: &'t str) -> Result<Vec<(Vec<MPPath<'a>>, Antecedents<'a>)>, MPError> {
+        let parse_tree = match kparser::KParser::parse(kparser::Rule::query, text) {
+            Ok(mut pairs) => pairs.next().expect("query parse tree"),
+            Err(err) => return Err(MPError::knowledge(text, err)),
+        };
+        let mut bound: HashSet<String> = HashSet::new();
+        let mut query: Vec<(Vec<MPPath<'a>>, Antecedents<'a>)> = vec![];
+        for pair in parse_tree.into_inner() {
+            match pair.as_rule() {
+                kparser::Rule::fact => {
+                    let offset = pair.as_span().start();
+                    self.bind_fact_vars(pair.as_str(), &mut bound).map_err(|err| err.within(text, offset))?;
+                    let paths = self.parse_fact(pair.as_str())?;
+                    query.push((paths, Antecedents {
+                        fact: None,
+                        negated: false,
+                        transforms: None,
+                        conditions: None,
+                    }));
+                },
+                kparser::Rule::transforms => {
+                    let offset = pair.as_span().start();
+                    let section = self.math.intern(pair.as_str());
+                    let compiled = TParser::compile(section, &self.lexicon, &mut bound)
+                        .map_err(|err| err.within(text, offset))?;
+                    query.last_mut().expect("a fact before transforms").1.transforms = Some(Rc::new(compiled));
+                },
+                kparser::Rule::conditions => {
+                    let offset = pair.as_span().start();
+                    let section = self.math.intern(pair.as_str());
+                    let compiled = CParser::compile(section, &self.lexicon, &bound)
+                        .map_err(|err| err.within(text, offset))?;
+                    query.last_mut().expect("a fact before conditions").1.conditions = Some(Rc::new(compiled));
+                },
+                _ => {}
             }
+        }
+        Ok(query)
+    }
 
-            fn visit_parse_node(&'a self,
-                                parse_tree: Pair<'a, Rule>,
-                                mut root_segments: Vec<&'a MPSegment>,
-                                mut all_paths: Vec<MPPath<'a>>,
-                                index: usize,
-                            ) -> Vec<MPPath> {
-                let text = parse_tree.as_str();
-                if text.is_empty() {
-                    return all_paths;
-                }
-                let rule = parse_tree.as_rule();
-                let name = format!("{:?}", rule);
-                let can_be_var = name.starts_with(constants::VAR_RANGE_PREFIX);
-                let mut children = parse_tree.into_inner().peekable();
-                let is_leaf = children.peek().is_none();
-                let segment = self.lexicon.intern_with_name(name, text, is_leaf);
-                root_segments.push(segment);
-                let root_ref: &Vec<&MPSegment> = unsafe { mem::transmute( &root_segments ) };
-                if can_be_var || is_leaf {
-                    let segments = root_segments;
-                    let new_path = MPPath::new(segments);
-                    all_paths.push(new_path);
-                }
-                let mut new_index = 0;
-                for child in children {
-                    all_paths = self.visit_parse_node(child,
-                                                      root_ref.clone(),
-                                                      all_paths,
-                                                      new_index);
-                    new_index += 1;
-                }
-                all_paths
-            }
-            pub fn substitute_fact(&'a self, fact: Vec<MPPath<'a>>, matching: MPMatching<'a>) -> (Vec<MPPath<'a>>, MPMatching<'a>, Option<String>) {
-                if matching.len() == 0 {
-                    return (fact, matching, None);
-                }
-                let (text, matching) = MPPath::substitute_paths_to_string(fact, matching);
+    /// Parse an `@include "..."` directive, and return the path in it.
+    pub fn parse_include(&self, text: &str) -> Result<String, MPError> {
+        match kparser::KParser::parse(kparser::Rule::include_directive, text.trim()) {
+            Ok(pairs) => {
+                let file = pairs.flatten()
+                    .find(|pair| pair.as_rule() == kparser::Rule::include_file)
+                    .expect("include file");
+                Ok(file.as_str().to_string())
+            },
+            Err(err) => Err(MPError::knowledge(text, err)),
+        }
+    }
+    /// Parse a single annotation, such as `@tag(finance)`, used to select rules.
+    pub fn parse_selector(&self, text: &str) -> Result<(String, String), MPError> {
+        match kparser::KParser::parse(kparser::Rule::selector, text.trim()) {
+            Ok(mut pairs) => self.annotation(pairs.next().expect("annotation pair")).map_err(|err| err.within(text, 0)),
+            Err(err) => Err(MPError::knowledge(text, err)),
+        }
+    }
+
+    fn annotation(&self, pair: Pair<kparser::Rule>) -> Result<(String, String), MPError> {
+        let text = pair.as_str();
+        let mut inner = pair.into_inner();
+        let key = inner.next().expect("annotation key").as_str();
+        let value = inner.next().expect("annotation value").as_str().trim();
+        if key != "name" && key != "tag" && key != "salience" {
+            return Err(MPError::KnowledgeSyntax {
+                text: text.to_string(),
+                line: 1,
+                col: 2,
+                message: format!("unknown annotation @{}, expected @name, @tag or @salience", key),
+            });
+        }
+        if key == "salience" && value.parse::<i32>().is_err() {
+            return Err(MPError::KnowledgeSyntax {
+                text: text.to_string(),
+                line: 1,
+                col: key.len() + 3,
+                message: format!("the salience must be an integer, not {}", value),
+            });
+        }
+        Ok((key.to_string(), value.to_string()))
+    }
 
-                let stext = unsafe { mem::transmute( text.as_str() ) };
-                
-                let parse_tree = FactParser::parse(Rule::fact, stext).ok().unwrap().next().expect("2nd fact pair");
-                (self.visit_parse_node(parse_tree,
-                                       vec![],
-                                       vec![],
-                                       0),
-                matching, Some(text))
+    pub fn parse_fact<'t>(&'a self, text: &'t str) -> Result<Vec<MPPath<'a>>, MPError> {
+        let parse_tree = match self.grammar.parse_fact(text) {
+            Ok(mut pairs) => pairs.next().expect("fact pair"),
+            Err(err) => return Err(MPError::fact(text, *err)),
+        };
+        Ok(self.visit_parse_node(parse_tree,
+                                 vec![],
+                                 vec![]))
+    }
 
+    pub fn matched_fact_key(&'a self, paths: &[MPPath<'a>], matching: &MPMatching<'a>) -> FactKey {
+        match tms::substituted_fact_key(paths, matching) {
+            Some(key) => key,
+            None => {
+                let (fact, _, _) = self.substitute_fact(paths.to_vec(), matching.clone()).expect("checked when matched");
+                tms::fact_key(&fact)
+            },
+        }
+    }
 
-            }
-            pub fn substitute_fact_fast(&'a self, fact: Vec<MPPath<'a>>, matching: MPMatching<'a>) -> Vec<MPPath<'a>> {
-                if matching.len() == 0 {
-                    return fact;
+    fn check_fact(&self, text: &str) -> Result<(), MPError> {
+        match self.grammar.parse_fact(text) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(MPError::fact(text, *err)),
+        }
+    }
+
+    fn bind_fact_vars(&self, text: &str, bound: &mut HashSet<String>) -> Result<(), MPError> {
+        match self.grammar.parse_fact(text) {
+            Ok(pairs) => {
+                for pair in pairs.flatten().filter(|pair| format!("{:?}", pair.as_rule()) == constants::VAR_RULE_NAME) {
+                    bound.insert(pair.as_str().to_string());
                 }
-                MPPath::substitute_paths_owning(fact, matching)
+                Ok(())
+            },
+            Err(err) => Err(MPError::fact(text, *err)),
+        }
+    }
+    /// Check that the variables in a negated antecedent are bound by the antecedents before it,
+    /// since it does not bind variables itself.
+    fn check_negation_vars(&self, text: &str, bound: &HashSet<String>) -> Result<(), MPError> {
+        let pairs = self.grammar.parse_fact(text).map_err(|err| MPError::fact(text, *err))?;
+        let var = pairs.flatten()
+            .filter(|pair| format!("{:?}", pair.as_rule()) == constants::VAR_RULE_NAME)
+            .find(|pair| !bound.contains(pair.as_str()));
+        match var {
+            Some(var) => {
+                let (line, col) = var.as_span().start_pos().line_col();
+                Err(MPError::KnowledgeSyntax {
+                    text: text.to_string(),
+                    line,
+                    col,
+                    message: format!("variable {} in a negated antecedent is not bound", var.as_str()),
+                })
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Whether some fact might match both `a` and `b`, comparing their parse trees
+    /// with the variables in either of them matching anything.
+    /// The bindings of repeated variables are not compared,
+    /// so it may answer true for facts that no fact matches.
+    pub fn may_unify(&self, a: &str, b: &str) -> bool {
+        match (self.grammar.parse_fact(a), self.grammar.parse_fact(b)) {
+            (Ok(mut a), Ok(mut b)) => match (a.next(), b.next()) {
+                (Some(a), Some(b)) => unify_trees(a, b),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn visit_parse_node<'t>(&'a self,
+                            parse_tree: Pair<'t, P::Rule<'a>>,
+                            mut root_segments: Vec<&'a MPSegment>,
+                            mut all_paths: Vec<MPPath<'a>>,
+                        ) -> Vec<MPPath<'a>> {
+        let text = parse_tree.as_str();
+        if text.is_empty() {
+            return all_paths;
+        }
+        let rule = parse_tree.as_rule();
+        let name = format!("{:?}", rule);
+        let can_be_var = name.starts_with(constants::VAR_RANGE_PREFIX);
+        let mut children = parse_tree.into_inner().peekable();
+        let is_leaf = children.peek().is_none();
+        let segment = self.lexicon.intern_with_name(name, text, is_leaf);
+        root_segments.push(segment);
+        let root_ref: &Vec<&MPSegment> = unsafe { mem::transmute( &root_segments ) };
+        if can_be_var || is_leaf {
+            let segments = root_segments;
+            let new_path = MPPath::new(segments);
+            all_paths.push(new_path);
+        }
+        for child in children {
+            all_paths = self.visit_parse_node(child,
+                                              root_ref.clone(),
+                                              all_paths);
+        }
+        all_paths
+    }
+    /// Substitute the variables in a fact, and parse it again.
+    /// It fails if some value, computed by a transform, does not fit the grammar where it is substituted.
+    pub fn substitute_fact(&'a self, fact: Vec<MPPath<'a>>, matching: MPMatching<'a>) -> Result<(Vec<MPPath<'a>>, MPMatching<'a>, Option<String>), MPError> {
+        if matching.is_empty() {
+            return Ok((fact, matching, None));
+        }
+        let (text, matching) = MPPath::substitute_paths_to_string(fact, matching);
+
+        let parse_tree = match self.grammar.parse_fact(text.as_str()) {
+            Ok(mut pairs) => pairs.next().expect("2nd fact pair"),
+            Err(err) => return Err(MPError::fact(&text, *err)),
+        };
+        Ok((self.visit_parse_node(parse_tree,
+                                  vec![],
+                                  vec![]),
+            matching, Some(text)))
+    }
+    pub fn substitute_fact_fast(&'a self, fact: Vec<MPPath<'a>>, matching: MPMatching<'a>) -> Vec<MPPath<'a>> {
+        if matching.is_empty() {
+            return fact;
+        }
+        MPPath::substitute_paths_owning(fact, matching)
+    }
+    pub fn normalize_fact (&'a self, fact: Vec<MPPath<'a>>) -> (MPMatching<'a>, Vec<MPPath<'a>>) {
+        let mut varmap: MPMatching<'a> = HashMap::new();
+        let mut invarmap: MPMatching<'a> = HashMap::new();
+        let mut counter = 1;
+        let leaves = fact.as_slice();
+        for path in leaves {
+            if path.value.is_empty || !path.value.is_leaf {
+                continue;
             }
-            pub fn normalize_fact (&'a self, fact: Vec<MPPath<'a>>) -> (MPMatching<'a>, Vec<MPPath<'a>>) {
-                let mut varmap: MPMatching<'a> = HashMap::new();
-                let mut invarmap: MPMatching<'a> = HashMap::new();
-                let mut counter = 1;
-                let leaves = fact.as_slice();
-                for path in leaves {
-                    if path.value.is_empty || !path.value.is_leaf {
-                        continue;
-                    }
-                    if path.value.is_var {
-                        let old_var = varmap.get(&path.value);
-                        if old_var.is_none() {
-                            let new_var = self.lexicon.make_var(counter);
-                            counter += 1;
-                            varmap.insert(path.value, new_var);
-                            invarmap.insert(new_var, path.value);
-                        }
-                    }
+            if path.value.is_var {
+                let old_var = varmap.get(&path.value);
+                if old_var.is_none() {
+                    let new_var = self.lexicon.make_var(counter);
+                    counter += 1;
+                    varmap.insert(path.value, new_var);
+                    invarmap.insert(new_var, path.value);
                 }
-                let new_fact = self.substitute_fact_fast(fact, varmap);
-                (invarmap, new_fact)
             }
         }
+        let new_fact = self.substitute_fact_fast(fact, varmap);
+        (invarmap, new_fact)
+    }
+}
+
+fn unify_trees<R: RuleType>(a: Pair<'_, R>, b: Pair<'_, R>) -> bool {
+    let (name_a, name_b) = (format!("{:?}", a.as_rule()), format!("{:?}", b.as_rule()));
+    if name_a == constants::VAR_RULE_NAME || name_b == constants::VAR_RULE_NAME {
+        return true;
     }
+    if name_a != name_b {
+        return false;
+    }
+    let (children_a, children_b) = (significant_children(a.clone()), significant_children(b.clone()));
+    if children_a.is_empty() && children_b.is_empty() {
+        return a.as_str() == b.as_str();
+    }
+    children_a.len() == children_b.len()
+        && children_a.into_iter().zip(children_b).all(|(a, b)| unify_trees(a, b))
+}
+
+// whitespace and comments may differ between facts that match
+fn significant_children<R: RuleType>(pair: Pair<'_, R>) -> Vec<Pair<'_, R>> {
+    pair.into_inner()
+        .filter(|child| !matches!(format!("{:?}", child.as_rule()).as_str(), "WHITESPACE" | "COMMENT"))
+        .collect()
 }
@@ -0,0 +1,93 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! Proofs of the facts in knowledge bases, as returned by `KnowledgeBase::why`.
+
+use std::fmt;
+
+use crate::bindings::Bindings;
+
+
+/// Why a fact is in a knowledge base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proof {
+    /// The text of the fact.
+    pub fact: String,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    /// The fact was told.
+    Told,
+    /// The fact is the negated antecedent of a rule,
+    /// which holds because there is no such fact in the knowledge base.
+    Absent,
+    /// The fact was derived by a rule (with its name, if it has one), with the given bindings,
+    /// from the proven facts that matched its antecedents, in order.
+    Derived { rule: String, name: Option<String>, bindings: Bindings, from: Vec<Proof> },
+    /// The fact was derived while provenance was not being recorded,
+    /// so only the facts it was derived from are known.
+    Supported { from: Vec<Proof> },
+}
+
+impl Proof {
+    /// The told facts (and absent facts) on which the proof rests.
+    pub fn leaves(&self) -> Vec<&Proof> {
+        match &self.reason {
+            Reason::Told | Reason::Absent => vec![self],
+            Reason::Derived { from, .. } | Reason::Supported { from } => {
+                from.iter().flat_map(|proof| proof.leaves()).collect()
+            },
+        }
+    }
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "    ".repeat(depth);
+        match &self.reason {
+            Reason::Told => writeln!(f, "{}{}  [told]", indent, self.fact),
+            Reason::Absent => writeln!(f, "{}¬ {}  [absent]", indent, self.fact),
+            Reason::Derived { rule, name, bindings, from } => {
+                match name {
+                    Some(name) => writeln!(f, "{}{}  [by {}: {}]", indent, self.fact, name, rule)?,
+                    None => writeln!(f, "{}{}  [by: {}]", indent, self.fact, rule)?,
+                }
+                if !bindings.is_empty() {
+                    writeln!(f, "{}  with: {}", indent, bindings)?;
+                }
+                for proof in from {
+                    proof.write(f, depth + 1)?;
+                }
+                Ok(())
+            },
+            Reason::Supported { from } => {
+                writeln!(f, "{}{}  [derived]", indent, self.fact)?;
+                for proof in from {
+                    proof.write(f, depth + 1)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl fmt::Display for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! Reading knowledge from readers, sentence by sentence.
+
+use std::io::BufRead;
+
+use crate::error::MPError;
+
+
+/// A sentence read, without the `◊` or `<>` that ends it,
+/// with the line and column where it starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sentence {
+    pub text: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// The sentences in the knowledge read from a reader,
+/// reading a line at a time. Comments are left out.
+/// Text after the last `◊` or `<>` is given as a last sentence.
+pub struct Sentences<R: BufRead> {
+    input: R,
+    buf: String,
+    pos: usize,
+    line: usize,
+    in_comment: bool,
+}
+
+impl<R: BufRead> Sentences<R> {
+    pub fn new(input: R) -> Sentences<R> {
+        Sentences {
+            input,
+            buf: String::new(),
+            pos: 0,
+            line: 0,
+            in_comment: false,
+        }
+    }
+    /// Add the text of the current line from `pos` up to `end` to the sentence.
+    fn take(&mut self, sentence: &mut Option<Sentence>, end: usize) {
+        let piece = &self.buf[self.pos..end];
+        match sentence {
+            Some(sentence) => sentence.text.push_str(piece),
+            None => {
+                if let Some(start) = piece.find(|c: char| !c.is_whitespace()) {
+                    let col = self.buf[..self.pos + start].chars().count() + 1;
+                    *sentence = Some(Sentence { text: piece[start..].to_string(), line: self.line, col });
+                }
+            },
+        }
+        self.pos = end;
+    }
+}
+
+impl<R: BufRead> Iterator for Sentences<R> {
+    type Item = Result<Sentence, MPError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut sentence: Option<Sentence> = None;
+        loop {
+            if self.pos >= self.buf.len() {
+                self.buf.clear();
+                self.pos = 0;
+                match self.input.read_line(&mut self.buf) {
+                    Ok(0) => return sentence.map(Ok),
+                    Ok(_) => self.line += 1,
+                    Err(err) => return Some(Err(MPError::read(err, self.line))),
+                }
+            }
+            let rest = &self.buf[self.pos..];
+            if self.in_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        self.pos += end + 2;
+                        self.in_comment = false;
+                    },
+                    None => {
+                        // keep the lines of the sentence in place
+                        if let Some(sentence) = sentence.as_mut() {
+                            sentence.text.push('\n');
+                        }
+                        self.pos = self.buf.len();
+                    },
+                }
+                continue;
+            }
+            let next = [rest.find("/*"), rest.find('◊'), rest.find("<>")];
+            match next.iter().enumerate().filter_map(|(i, found)| found.map(|at| (at, i))).min() {
+                None => {
+                    let end = self.buf.len();
+                    self.take(&mut sentence, end);
+                },
+                Some((at, 0)) => {
+                    let end = self.pos + at;
+                    self.take(&mut sentence, end);
+                    self.pos += 2;
+                    self.in_comment = true;
+                },
+                Some((at, kind)) => {
+                    let end = self.pos + at;
+                    self.take(&mut sentence, end);
+                    self.pos += if kind == 1 { '◊'.len_utf8() } else { 2 };
+                    if sentence.is_some() {
+                        return sentence.map(Ok);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// What was added by a bulk load.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BulkLoad {
+    /// The facts that were new to the knowledge base.
+    pub added: usize,
+    /// The facts that were already in the knowledge base, or repeated in the input.
+    pub duplicates: usize,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sentences() {
+        let input = "a ∈ b ◊ /* c ∈ d ◊ */\n  e ∈ f\n   ∧ g ∈ h <>\n/* multi\nline */ i ∈ j";
+        let sentences: Vec<Sentence> = Sentences::new(input.as_bytes()).map(Result::unwrap).collect();
+        let found: Vec<(&str, usize, usize)> = sentences.iter()
+            .map(|sentence| (sentence.text.trim_end(), sentence.line, sentence.col))
+            .collect();
+        assert_eq!(found, vec![("a ∈ b", 1, 1), ("e ∈ f\n   ∧ g ∈ h", 2, 3), ("i ∈ j", 5, 9)]);
+    }
+}
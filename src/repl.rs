@@ -0,0 +1,293 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! An interactive shell for knowledge bases with any grammar.
+//!
+//! Lines are told to the knowledge base, and sentences can span several lines,
+//! until they end with `◊` or `<>`. Lines starting with `?` are queries,
+//! and lines starting with `:` are commands, listed by `:help`.
+
+use std::cell::RefCell;
+use std::io::{ self, BufRead, Write };
+use std::rc::Rc;
+
+use crate::bindings::Bindings;
+use crate::grammar::FactGrammar;
+use crate::kbase::KBase;
+use crate::knowledge::KnowledgeBase;
+
+
+const HELP: &str = "\
+<sentence> ◊        tell a fact or a rule
+? <query>           ask a query, e.g. ? <X> ∈ human
+:load <file>        tell the knowledge in a file
+:bulk <file>        load the facts in a file in bulk
+:retract <fact>     retract a told fact
+:why <fact>         explain a fact
+:facts              list the facts
+:rules              list the rules
+:stats              show statistics
+:help               show this help
+:quit               leave the shell";
+
+/// Run a shell on `kb`, reading from `input` and writing to `output`,
+/// until the input ends or `:quit` is read.
+/// `:why` can only explain how facts were derived if the knowledge base
+/// records provenance, see `KnowledgeBase::record_provenance`.
+pub fn run<P, R, W>(kb: &KnowledgeBase<P>, input: R, output: W) -> io::Result<()>
+    where P: FactGrammar + 'static, R: BufRead, W: Write
+{
+    let outputs = Rc::new(RefCell::new(vec![]));
+    let sink = outputs.clone();
+    let handler = kb.on_output(move |out| sink.borrow_mut().push(out.text.clone()));
+    let result = session(kb, input, output, &outputs);
+    kb.remove_output(handler);
+    result
+}
+
+fn session<P, R, W>(kb: &KnowledgeBase<P>, input: R, mut output: W, outputs: &Outputs) -> io::Result<()>
+    where P: FactGrammar + 'static, R: BufRead, W: Write
+{
+    let mut sentence = String::new();
+    let mut lines = input.lines();
+    loop {
+        write!(output, "{}", if sentence.is_empty() { "mp> " } else { "... " })?;
+        output.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let trimmed = line.trim();
+        if sentence.is_empty() {
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(query) = trimmed.strip_prefix('?') {
+                ask(kb, query, &mut output)?;
+                continue;
+            }
+            if let Some(command) = trimmed.strip_prefix(':') {
+                if !execute(kb, command, outputs, &mut output)? {
+                    break;
+                }
+                continue;
+            }
+        }
+        sentence.push_str(&line);
+        sentence.push('\n');
+        if trimmed.ends_with('◊') || trimmed.ends_with("<>") {
+            if let Err(err) = kb.tell(&sentence) {
+                writeln!(output, "{}", err)?;
+            }
+            report(kb, outputs, &mut output)?;
+            sentence.clear();
+        }
+    }
+    Ok(())
+}
+
+/// Run a command, and return whether the shell goes on.
+fn execute<P, W>(kb: &KnowledgeBase<P>, command: &str, outputs: &Outputs, output: &mut W) -> io::Result<bool>
+    where P: FactGrammar + 'static, W: Write
+{
+    let (name, arg) = match command.find(char::is_whitespace) {
+        Some(at) => (&command[..at], command[at..].trim()),
+        None => (command, ""),
+    };
+    match name {
+        "load" => {
+            if let Err(err) = kb.tell_file(arg) {
+                writeln!(output, "{}", err)?;
+            }
+            report(kb, outputs, output)?;
+        },
+        "bulk" => {
+            let loaded = std::fs::File::open(arg)
+                .map_err(|err| err.to_string())
+                .and_then(|file| kb.load_facts_from(file).map_err(|err| err.to_string()));
+            match loaded {
+                Ok(loaded) => writeln!(output, "{} facts added, {} duplicates", loaded.added, loaded.duplicates)?,
+                Err(err) => writeln!(output, "{}", err)?,
+            }
+            report(kb, outputs, output)?;
+        },
+        "retract" => {
+            if let Err(err) = kb.retract(&sentence(arg)) {
+                writeln!(output, "{}", err)?;
+            }
+        },
+        "why" => {
+            match kb.why(arg) {
+                Ok(Some(proof)) => writeln!(output, "{}", proof)?,
+                Ok(None) => writeln!(output, "not in the knowledge base")?,
+                Err(err) => writeln!(output, "{}", err)?,
+            }
+        },
+        "facts" => {
+            for fact in kb.facts() {
+                writeln!(output, "{} ◊", fact)?;
+            }
+        },
+        "rules" => {
+            for rule in kb.rules() {
+                let mut label = format!("#{}", rule.id);
+                if let Some(name) = &rule.name {
+                    label.push_str(&format!(" {}", name));
+                }
+                for tag in rule.tags.iter() {
+                    label.push_str(&format!(" @tag({})", tag));
+                }
+                if !rule.enabled {
+                    label.push_str(" (disabled)");
+                }
+                writeln!(output, "{}: fired {} times, {} partial matches", label, rule.fired, rule.partial.len())?;
+                for line in rule.source.lines() {
+                    writeln!(output, "    {}", line.trim())?;
+                }
+            }
+        },
+        "stats" => {
+            let rules = kb.rules();
+            writeln!(output, "facts: {}", kb.facts().len())?;
+            writeln!(output, "rules: {}", rules.len())?;
+            writeln!(output, "partial matches: {}", rules.iter().map(|rule| rule.partial.len()).sum::<usize>())?;
+            writeln!(output, "rules fired: {}", rules.iter().map(|rule| rule.fired).sum::<usize>())?;
+            writeln!(output, "pending activations: {}", kb.queues().len())?;
+            writeln!(output, "diagnostics: {}", kb.diagnostics().len())?;
+        },
+        "help" => writeln!(output, "{}", HELP)?,
+        "quit" | "q" => return Ok(false),
+        _ => writeln!(output, "unknown command :{}, try :help", name)?,
+    }
+    Ok(true)
+}
+
+fn ask<P, W>(kb: &KnowledgeBase<P>, query: &str, output: &mut W) -> io::Result<()>
+    where P: FactGrammar + 'static, W: Write
+{
+    match kb.ask(&sentence(query)) {
+        Ok(answers) => write_table(&answers, output),
+        Err(err) => writeln!(output, "{}", err),
+    }
+}
+
+/// Write the answers to a query as a table, with a column per variable.
+fn write_table<W: Write>(answers: &[Bindings], output: &mut W) -> io::Result<()> {
+    if answers.is_empty() {
+        return writeln!(output, "no");
+    }
+    let vars: Vec<&str> = answers[0].iter().map(|(var, _)| var).collect();
+    if vars.is_empty() {
+        return writeln!(output, "yes");
+    }
+    let mut widths: Vec<usize> = vars.iter().map(|var| var.chars().count()).collect();
+    for answer in answers {
+        for (i, (_, val)) in answer.iter().enumerate() {
+            widths[i] = widths[i].max(val.chars().count());
+        }
+    }
+    let row = |cells: Vec<&str>| -> String {
+        let cells: Vec<String> = cells.iter().zip(widths.iter())
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell.chars().count())))
+            .collect();
+        cells.join(" | ").trim_end().to_string()
+    };
+    writeln!(output, "{}", row(vars.clone()))?;
+    let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    writeln!(output, "{}", rule.join("-+-"))?;
+    for answer in answers {
+        writeln!(output, "{}", row(answer.iter().map(|(_, val)| val).collect()))?;
+    }
+    writeln!(output, "({} answers)", answers.len())
+}
+
+/// The outputs of rules not yet written.
+type Outputs = Rc<RefCell<Vec<String>>>;
+
+/// Write the outputs of rules, and the diagnostics, produced by the last knowledge told.
+fn report<P, W>(kb: &KnowledgeBase<P>, outputs: &Outputs, output: &mut W) -> io::Result<()>
+    where P: FactGrammar + 'static, W: Write
+{
+    for text in outputs.borrow_mut().drain(..) {
+        writeln!(output, "{}", text)?;
+    }
+    for diagnostic in kb.take_diagnostics() {
+        writeln!(output, "{}", diagnostic)?;
+    }
+    Ok(())
+}
+
+/// The text with a final `◊`, if it has none.
+fn sentence(text: &str) -> String {
+    let text = text.trim();
+    if text.ends_with('◊') || text.ends_with("<>") {
+        text.to_string()
+    } else {
+        format!("{} ◊", text)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Grammar;
+
+    const GRAMMAR: &str = r#"
+        var         = @{ ("<" ~ &ASCII_ALPHA_UPPER) ~ ASCII_ALPHA_UPPER ~ ASCII_ALPHA* ~ ('0'..'9')* ~ ">" }
+        fact        = _{ SOI ~ rfact ~ EOI }
+        rfact       = { var_range ~ pred ~ var_range }
+        pred        = @{ "∈" | "⊆" }
+        v_name      = @{ ASCII_ALPHANUMERIC+ }
+        var_range   = _{ v_name | var }
+        WHITESPACE  = { (" " | "\t" | NEWLINE)+ }
+    "#;
+
+    #[test]
+    fn test_session() {
+        let kb = KnowledgeBase::new(Grammar::new(GRAMMAR).unwrap());
+        kb.record_provenance(true);
+        let input = "\
+<X1> ∈ human
+    → <X1> ∈ mortal ◊
+<X1> ∈ mortal → {<{ <X1> ∈ dead }>} ◊
+socrates ∈ human ◊
+? <Who> ∈ mortal
+? plato ∈ mortal
+:why socrates ∈ mortal
+:stats
+:quit
+plato ∈ human ◊
+";
+        let mut output = vec![];
+        run(&kb, input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<Who>\n--------\nsocrates\n(1 answers)"), "{}", output);
+        assert!(output.contains("mp> no\n"), "{}", output);
+        assert!(output.contains("facts: 2\nrules: 2\n"), "{}", output);
+        assert!(output.contains("mp> socrates ∈ dead\n"), "{}", output);
+        assert!(output.contains("socrates ∈ human"), "{}", output);
+        assert!(kb.ask("plato ∈ human ◊").unwrap().is_empty());
+
+        // a second session on the same knowledge base gets its own outputs
+        let mut output = vec![];
+        run(&kb, "plato ∈ human ◊\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "mp> plato ∈ dead\nmp> ");
+    }
+}
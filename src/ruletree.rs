@@ -21,17 +21,64 @@ use std::clone::Clone;
 use std::collections::{ HashMap, VecDeque };
 use std::fmt;
 use std::mem;
+use std::rc::Rc;
 //use std::collections::VecDeque;
 use std::cell::{ RefCell, Cell };
 
 //use log::debug;
 
+use crate::arena::Arena;
 use crate::constants;
 use crate::path::MPPath;
 use crate::segment::MPSegment;
 use crate::matching::MPMatching;
+use crate::transform::Transforms;
+use crate::condition::Conditions;
+use crate::tms::FactKey;
+use crate::bindings::Bindings;
 
 
+/// Identifies a told rule in a knowledge base.
+pub type RuleId = u64;
+
+/// The annotations of a rule, `@name(...)`, `@salience(...)` and any number of `@tag(...)`,
+/// and its specificity.
+#[derive(Debug, Default, PartialEq)]
+pub struct RuleMeta<'a> {
+    pub name: Option<&'a str>,
+    pub tags: Vec<&'a str>,
+    /// Rules with higher salience fire first.
+    pub salience: i32,
+    /// The number of antecedents and conditions of the rule.
+    pub specificity: usize,
+}
+
+/// A told rule, as listed by `KnowledgeBase::rules`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleInfo {
+    pub id: RuleId,
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    pub salience: i32,
+    /// The text of the rule, as it was told.
+    pub source: String,
+    /// Whether the rule is matched against new facts.
+    pub enabled: bool,
+    /// The number of times that the rule has matched all its antecedents.
+    pub fired: usize,
+    /// The rules obtained matching some of its antecedents.
+    pub partial: Vec<PartialRule>,
+}
+
+/// A rule with some of its antecedents matched, waiting for the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialRule {
+    /// The number of antecedents still to be matched.
+    pub remaining: usize,
+    /// The variables bound by the matched antecedents.
+    pub bindings: Bindings,
+}
+
 pub type Response<'a> = Vec<(&'a RefCell<Vec<RuleRef<'a>>>, MPMatching<'a>)>;
 
 pub fn new_response<'a>() -> Response<'a> {
@@ -41,8 +88,25 @@ pub fn new_response<'a>() -> Response<'a> {
 #[derive(Debug, Clone)]
 pub struct Antecedents<'a> {
     pub fact: Option<&'a str>,
-    pub transforms: &'a str,
-    pub conditions: &'a str,
+    /// Whether the antecedent is satisfied by the absence of facts matching `fact`.
+    pub negated: bool,
+    pub transforms: Option<Rc<Transforms<'a>>>,
+    pub conditions: Option<Rc<Conditions<'a>>>,
+}
+
+impl<'a> Antecedents<'a> {
+    pub fn transforms_source(&self) -> &'a str {
+        match &self.transforms {
+            Some(transforms) => transforms.source,
+            None => "",
+        }
+    }
+    pub fn conditions_source(&self) -> &'a str {
+        match &self.conditions {
+            Some(conditions) => conditions.source,
+            None => "",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -52,12 +116,31 @@ pub struct MPRule<'a> {
     pub consequents: Vec<&'a str>,
     pub matched: MPMatching<'a>,
     pub output: Option<&'a str>,
+    pub source: &'a str,
+    pub support: Vec<FactKey>,
+    /// The id given to the rule when it was told,
+    /// shared by the partially matched rules obtained from it.
+    pub id: RuleId,
+    pub meta: Rc<RuleMeta<'a>>,
+}
+
+impl<'a> MPRule<'a> {
+    /// The name of the rule if it has one, or else its id, for messages.
+    pub fn label(&self) -> String {
+        match self.meta.name {
+            Some(name) => name.to_string(),
+            None => format!("#{}", self.id),
+        }
+    }
 }
 
 impl<'a> fmt::Display for MPRule<'a> {
 
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut string = String::from("rule\n\n");
+        let mut string = format!("rule {}\n\n", self.label());
+        if self.antecedents.negated {
+            string.push_str("¬ ");
+        }
         string.push_str(
             match self.antecedents.fact {
                 Some(fact) => fact,
@@ -65,18 +148,21 @@ impl<'a> fmt::Display for MPRule<'a> {
             }
         );
         string.push_str("\n{={\n");
-        string.push_str(self.antecedents.transforms);
+        string.push_str(self.antecedents.transforms_source());
         string.push_str("}=}\n{?{\n");
-        string.push_str(self.antecedents.conditions);
+        string.push_str(self.antecedents.conditions_source());
         string.push_str("\n}?}\n->\n");
         for more_ants in &self.more_antecedents {
+            if more_ants.negated {
+                string.push_str("¬ ");
+            }
             if more_ants.fact.is_some() {
                 string.push_str(more_ants.fact.unwrap());
             }
             string.push_str("{={\n");
-            string.push_str(more_ants.transforms);
+            string.push_str(more_ants.transforms_source());
             string.push_str("}=}\n{?{\n");
-            string.push_str(more_ants.conditions);
+            string.push_str(more_ants.conditions_source());
             string.push_str("}?}\n->\n");
         }
 
@@ -121,18 +207,27 @@ struct UVarChild<'a> {
 
 pub struct RuleSet<'a> {
     pub root: RSNode<'a>,
+    root_path: Box<MPPath<'a>>,
+    paths: Arena<MPPath<'a>>,
+    nodes: Arena<RSNode<'a>>,
 }
 
 impl<'a> RuleSet<'a> {
 
     pub fn new(root_path: MPPath<'a>) -> Self {
-        let root_path_ref = Box::leak(Box::new(root_path));
+        let root_path = Box::new(root_path);
+        // SAFETY: the path is boxed, so it does not move with the rule set,
+        // and it is only dropped along with the rule set, after the root that refers to it.
+        let root_path_ref = unsafe { mem::transmute::<&MPPath<'a>, &'a MPPath<'a>>( &*root_path ) };
         let root = RSNode::new(root_path_ref, 1);
         RuleSet {
             root,
+            root_path,
+            paths: Arena::new(),
+            nodes: Arena::new(),
         }
     }
-    pub fn follow_and_create_paths(&'a self, mut paths: Vec<MPPath<'a>>, rule_ref: RuleRef<'a>, mut depth: usize) {
+    pub fn follow_and_create_paths(&'a self, mut paths: Vec<MPPath<'a>>, rule_ref: RuleRef<'a>, mut depth: usize) -> &'a RSNode<'a> {
         let mut parent: &RSNode = &self.root; 
         let mut child: Option<&RSNode>; 
         let mut visited_vars: Vec<&MPSegment> = vec![];
@@ -173,6 +268,7 @@ impl<'a> RuleSet<'a> {
 
         parent.end_node.set(true);
         parent.rule_refs.borrow_mut().push(rule_ref);
+        parent
     }
 
     fn create_paths(&'a self, mut parent: &'a RSNode<'a>, mut paths: Vec<MPPath<'a>>, mut visited: Vec<&'a MPSegment>, mut depth: usize) -> &'a RSNode {
@@ -183,8 +279,8 @@ impl<'a> RuleSet<'a> {
             }
             depth += 1;
             let val = new_path.value;
-            let path_ref = Box::leak(Box::new(new_path));
-            let child_ref = Box::leak(Box::new(RSNode::new(path_ref, depth)));
+            let path_ref = self.paths.alloc(new_path);
+            let child_ref = self.nodes.alloc(RSNode::new(path_ref, depth));
             if val.is_var {
                 if visited.contains(&val) {
                     parent.var_children.borrow_mut().insert(path_ref, child_ref);
@@ -200,6 +296,43 @@ impl<'a> RuleSet<'a> {
         parent.end_node.set(true);
         parent
     }
+    /// Every rule in the tree, with the paths that lead to its node,
+    /// from which `follow_and_create_paths` would rebuild it.
+    pub fn rule_refs(&'a self) -> Vec<(Vec<MPPath<'a>>, RuleRef<'a>)> {
+        let mut found = vec![];
+        let mut pending: Vec<(&'a RSNode<'a>, Vec<MPPath<'a>>)> = vec![(&self.root, vec![])];
+        while let Some((node, paths)) = pending.pop() {
+            for rule_ref in node.rule_refs.borrow().iter() {
+                found.push((paths.clone(), rule_ref.clone()));
+            }
+            let mut children: Vec<&'a RSNode<'a>> = node.children.borrow().values().cloned().collect();
+            children.extend(node.var_children.borrow().values());
+            children.extend(node.get_var_child());
+            for child in children {
+                let mut child_paths = paths.clone();
+                child_paths.push(child.path.clone());
+                pending.push((child, child_paths));
+            }
+        }
+        found
+    }
+    /// Remove from the tree the rules for which `remove` returns true,
+    /// and return how many were removed.
+    pub fn remove_rule_refs<F: FnMut(&RuleRef<'a>) -> bool>(&'a self, mut remove: F) -> usize {
+        let mut removed = 0;
+        let mut pending: Vec<&'a RSNode<'a>> = vec![&self.root];
+        while let Some(node) = pending.pop() {
+            let before = node.rule_refs.borrow().len();
+            if before > 0 {
+                node.retain_rule_refs(|rule_ref| !remove(rule_ref));
+                removed += before - node.rule_refs.borrow().len();
+            }
+            pending.extend(node.children.borrow().values());
+            pending.extend(node.var_children.borrow().values());
+            pending.extend(node.get_var_child());
+        }
+        removed
+    }
     pub fn query_paths(&'a self, paths: Vec<MPPath<'a>>) -> (Response, Vec<MPPath<'a>>) {
         let response = new_response();
         let matched: MPMatching = HashMap::new();
@@ -269,6 +402,14 @@ impl<'a> RSNode<'a> {
         }
     }
 
+    pub fn retain_rule_refs<F: FnMut(&RuleRef<'a>) -> bool>(&self, keep: F) {
+        let mut rule_refs = self.rule_refs.borrow_mut();
+        rule_refs.retain(keep);
+        if rule_refs.is_empty() {
+            self.end_node.set(false);
+        }
+    }
+
     pub fn climb(&'a self,
                  mut paths: &'a [MPPath<'a>],
                  mut response: Response<'a>,
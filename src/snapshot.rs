@@ -0,0 +1,276 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+//! The binary format of knowledge base snapshots.
+//!
+//! A snapshot starts with `MAGIC` and the format `VERSION`,
+//! followed by the source of the grammar of the knowledge base,
+//! the table of the segments used in the rest of the snapshot,
+//! and the body, where segments are referred to by their index in the table.
+//! Integers are little endian, and strings are preceded by their length in bytes.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{ self, Read, Write };
+
+use crate::lexicon::Lexicon;
+use crate::matching::MPMatching;
+use crate::path::MPPath;
+use crate::segment::MPSegment;
+
+
+pub const MAGIC: &[u8; 8] = b"MPSNAPSH";
+pub const VERSION: u32 = 6;
+
+
+/// Errors saving or loading snapshots.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The data is not a snapshot, or it is corrupt.
+    Format(String),
+    /// The snapshot was written in a format version that this build cannot read.
+    Version(u32),
+    /// The snapshot was taken from a knowledge base with a different grammar.
+    Grammar,
+    /// The journal of the knowledge base is corrupt, or cannot be replayed.
+    Journal(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot i/o error: {}", err),
+            SnapshotError::Format(message) => write!(f, "malformed snapshot: {}", message),
+            SnapshotError::Version(version) => write!(f, "unsupported snapshot version {} (expected {})", version, VERSION),
+            SnapshotError::Grammar => write!(f, "the snapshot was taken with a different grammar"),
+            SnapshotError::Journal(message) => write!(f, "bad journal: {}", message),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+
+/// Encodes the body of a snapshot, collecting the segments it refers to.
+pub struct Encoder<'a> {
+    buf: Vec<u8>,
+    index: HashMap<*const MPSegment, u32>,
+    segments: Vec<&'a MPSegment>,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new() -> Encoder<'a> {
+        Encoder {
+            buf: vec![],
+            index: HashMap::new(),
+            segments: vec![],
+        }
+    }
+    pub fn u32(&mut self, n: u32) {
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+    pub fn u64(&mut self, n: u64) {
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+    pub fn count(&mut self, n: usize) {
+        self.u32(n as u32);
+    }
+    pub fn bool(&mut self, b: bool) {
+        self.buf.push(b as u8);
+    }
+    pub fn str(&mut self, s: &str) {
+        self.count(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+    pub fn segment(&mut self, segment: &'a MPSegment) {
+        let next = self.segments.len() as u32;
+        let index = *self.index.entry(segment as *const MPSegment).or_insert(next);
+        if index == next {
+            self.segments.push(segment);
+        }
+        self.u32(index);
+    }
+    pub fn path(&mut self, path: &MPPath<'a>) {
+        self.count(path.segments.len());
+        for segment in path.segments.iter() {
+            self.segment(segment);
+        }
+    }
+    pub fn paths(&mut self, paths: &[MPPath<'a>]) {
+        self.count(paths.len());
+        for path in paths {
+            self.path(path);
+        }
+    }
+    pub fn matching(&mut self, matching: &MPMatching<'a>) {
+        self.count(matching.len());
+        for (var, value) in matching.iter() {
+            self.segment(var);
+            self.segment(value);
+        }
+    }
+    pub fn keys(&mut self, keys: &[u64]) {
+        self.count(keys.len());
+        for key in keys {
+            self.u64(*key);
+        }
+    }
+    /// Write the whole snapshot.
+    pub fn finish<W: Write>(self, grammar: &str, mut out: W) -> Result<(), SnapshotError> {
+        let mut head = Encoder::new();
+        head.str(grammar);
+        head.count(self.segments.len());
+        for segment in self.segments.iter() {
+            head.str(&segment.name);
+            head.str(&segment.text);
+            head.bool(segment.is_leaf);
+        }
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&head.buf)?;
+        out.write_all(&self.buf)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a> Default for Encoder<'a> {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+
+/// Decodes a snapshot, interning its segments in a lexicon.
+pub struct Decoder<'a, R: Read> {
+    input: R,
+    segments: Vec<&'a MPSegment>,
+}
+
+impl<'a, R: Read> Decoder<'a, R> {
+    /// Read the header of a snapshot, checking that it was taken
+    /// with the given grammar, and the table of segments.
+    pub fn new(mut input: R, grammar: &str, lexicon: &'a Lexicon) -> Result<Decoder<'a, R>, SnapshotError> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::Format(String::from("not a modus_ponens snapshot")));
+        }
+        let mut decoder = Decoder { input, segments: vec![] };
+        let version = decoder.u32()?;
+        if version != VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        if decoder.string()? != grammar {
+            return Err(SnapshotError::Grammar);
+        }
+        let n = decoder.count()?;
+        for _ in 0..n {
+            let name = decoder.string()?;
+            let text = decoder.string()?;
+            let is_leaf = decoder.bool()?;
+            let segment = lexicon.intern_with_name(name, &text, is_leaf);
+            decoder.segments.push(segment);
+        }
+        Ok(decoder)
+    }
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut bytes = [0u8; 4];
+        self.input.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut bytes = [0u8; 8];
+        self.input.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+    pub fn count(&mut self) -> Result<usize, SnapshotError> {
+        Ok(self.u32()? as usize)
+    }
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        let mut byte = [0u8; 1];
+        self.input.read_exact(&mut byte)?;
+        match byte[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(SnapshotError::Format(format!("bad boolean {}", other))),
+        }
+    }
+    pub fn string(&mut self) -> Result<String, SnapshotError> {
+        let n = self.count()?;
+        let mut bytes = Vec::new();
+        (&mut self.input).take(n as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != n {
+            return Err(SnapshotError::Format(String::from("truncated string")));
+        }
+        String::from_utf8(bytes).map_err(|_| SnapshotError::Format(String::from("bad utf-8 string")))
+    }
+    pub fn segment(&mut self) -> Result<&'a MPSegment, SnapshotError> {
+        let index = self.count()?;
+        match self.segments.get(index) {
+            Some(segment) => Ok(*segment),
+            None => Err(SnapshotError::Format(format!("bad segment index {}", index))),
+        }
+    }
+    pub fn path(&mut self) -> Result<MPPath<'a>, SnapshotError> {
+        let n = self.count()?;
+        if n == 0 {
+            return Err(SnapshotError::Format(String::from("empty path")));
+        }
+        let mut segments = vec![];
+        for _ in 0..n {
+            segments.push(self.segment()?);
+        }
+        Ok(MPPath::new(segments))
+    }
+    pub fn paths(&mut self) -> Result<Vec<MPPath<'a>>, SnapshotError> {
+        let n = self.count()?;
+        let mut paths = vec![];
+        for _ in 0..n {
+            paths.push(self.path()?);
+        }
+        Ok(paths)
+    }
+    pub fn matching(&mut self) -> Result<MPMatching<'a>, SnapshotError> {
+        let n = self.count()?;
+        let mut matching = HashMap::new();
+        for _ in 0..n {
+            let var = self.segment()?;
+            let value = self.segment()?;
+            matching.insert(var, value);
+        }
+        Ok(matching)
+    }
+    pub fn keys(&mut self) -> Result<Vec<u64>, SnapshotError> {
+        let n = self.count()?;
+        let mut keys = vec![];
+        for _ in 0..n {
+            keys.push(self.u64()?);
+        }
+        Ok(keys)
+    }
+}
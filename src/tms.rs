@@ -0,0 +1,433 @@
+// Copyright (c) 2020 by Enrique Pérez Arnaud <enrique at cazalla.net>    
+//    
+// This file is part of the modus_ponens project.    
+// http://www.modus_ponens.net    
+//    
+// The modus_ponens project is free software: you can redistribute it and/or modify    
+// it under the terms of the GNU General Public License as published by    
+// the Free Software Foundation, either version 3 of the License, or    
+// (at your option) any later version.    
+//    
+// The modus_ponens project is distributed in the hope that it will be useful,    
+// but WITHOUT ANY WARRANTY; without even the implied warranty of    
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the    
+// GNU General Public License for more details.    
+//    
+// You should have received a copy of the GNU General Public License    
+// along with any part of the modus_ponens project.    
+// If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{ HashMap, HashSet };
+use std::hash::Hasher;
+use std::cell::{ Cell, RefCell };
+
+use crate::path::MPPath;
+use crate::segment::MPSegment;
+use crate::matching::MPMatching;
+use crate::ruletree::RSNode;
+use crate::bindings::Bindings;
+use crate::proof::{ Proof, Reason };
+
+
+/// Identifies a fact in the justification records.
+/// It is built from the paths to the leaves of the fact,
+/// so it does not depend on whitespace.
+/// Keys are kept in snapshots, so they are hashed with `Fnv`,
+/// which does not change across builds.
+pub type FactKey = u64;
+
+pub fn fact_key(paths: &[MPPath]) -> FactKey {
+    let mut hasher = Fnv::new();
+    for path in paths {
+        if !path.value.is_empty && path.value.is_leaf {
+            hash_path(&path.segments[..path.len() - 1], path.value, &mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// The key standing for the absence of facts matching the given paths,
+/// on which the conclusions of rules with negated antecedents depend.
+pub fn negation_key(paths: &[MPPath]) -> FactKey {
+    let mut hasher = Fnv::new();
+    hasher.write("¬".as_bytes());
+    hasher.write(&fact_key(paths).to_le_bytes());
+    hasher.finish()
+}
+
+/// The key of the fact obtained substituting `matching` in `paths`.
+/// Returns `None` if some variable is bound to a non leaf segment,
+/// in which case the substituted fact has to be parsed to get the key.
+pub fn substituted_fact_key(paths: &[MPPath], matching: &MPMatching) -> Option<FactKey> {
+    let mut hasher = Fnv::new();
+    for path in paths {
+        if path.value.is_empty || !path.value.is_leaf {
+            continue;
+        }
+        let value = match matching.get(path.value) {
+            Some(value) if !value.is_leaf => return None,
+            Some(value) => value,
+            None => path.value,
+        };
+        hash_path(&path.segments[..path.len() - 1], value, &mut hasher);
+    }
+    Some(hasher.finish())
+}
+
+/// Hash the path made of the segments in `prefix` and `value`,
+/// by the names of its segments and the text of its value.
+fn hash_path(prefix: &[&MPSegment], value: &MPSegment, hasher: &mut Fnv) {
+    for segment in prefix.iter().chain(std::iter::once(&value)) {
+        hasher.write(segment.name.as_bytes());
+        hasher.write_u8(0xff);
+    }
+    hasher.write(value.text.as_bytes());
+    hasher.write_u8(0xff);
+}
+
+/// The FNV-1a hash, whose output does not depend on the build,
+/// unlike that of `DefaultHasher`.
+pub struct Fnv(u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv::new()
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+
+/// A recorded application of a rule.
+pub struct Derivation<'a> {
+    pub rule: &'a str,
+    pub name: Option<&'a str>,
+    pub support: Vec<FactKey>,
+    pub bindings: Bindings,
+}
+
+/// Records of why each fact is in a knowledge base:
+/// because it was told, or because it was derived from other facts
+/// (possibly in several ways).
+pub struct Justifications<'a> {
+    texts: RefCell<HashMap<FactKey, &'a str>>,
+    told: RefCell<HashSet<FactKey>>,
+    supports: RefCell<HashMap<FactKey, Vec<Vec<FactKey>>>>,
+    dependents: RefCell<HashMap<FactKey, HashSet<FactKey>>>,
+    rule_nodes: RefCell<HashMap<FactKey, Vec<&'a RSNode<'a>>>>,
+    derivations: RefCell<HashMap<FactKey, Vec<Derivation<'a>>>>,
+    absent: RefCell<HashMap<FactKey, &'a str>>,
+    times: RefCell<HashMap<FactKey, u64>>,
+    clock: Cell<u64>,
+}
+
+impl<'a> Justifications<'a> {
+    pub fn new() -> Justifications<'a> {
+        Justifications {
+            texts: RefCell::new(HashMap::new()),
+            told: RefCell::new(HashSet::new()),
+            supports: RefCell::new(HashMap::new()),
+            dependents: RefCell::new(HashMap::new()),
+            rule_nodes: RefCell::new(HashMap::new()),
+            derivations: RefCell::new(HashMap::new()),
+            absent: RefCell::new(HashMap::new()),
+            times: RefCell::new(HashMap::new()),
+            clock: Cell::new(0),
+        }
+    }
+    /// Keep the text of a fact that has been added to the knowledge base.
+    pub fn add_text(&self, fact: FactKey, text: &'a str) {
+        self.texts.borrow_mut().insert(fact, text);
+    }
+    /// Record the time at which a fact, or a negation, started to hold.
+    pub fn stamp(&self, fact: FactKey) {
+        let time = self.clock.get() + 1;
+        self.clock.set(time);
+        self.times.borrow_mut().insert(fact, time);
+    }
+    /// The times at which the given facts started to hold.
+    pub fn times(&self, facts: &[FactKey]) -> Vec<u64> {
+        let times = self.times.borrow();
+        facts.iter().map(|fact| times.get(fact).cloned().unwrap_or(0)).collect()
+    }
+    /// Whether the fact is in the knowledge base, or the negation holds.
+    pub fn holds(&self, fact: FactKey) -> bool {
+        self.texts.borrow().contains_key(&fact) || self.told.borrow().contains(&fact)
+    }
+    pub fn tell(&self, fact: FactKey) {
+        self.told.borrow_mut().insert(fact);
+    }
+    pub fn is_told(&self, fact: FactKey) -> bool {
+        self.told.borrow().contains(&fact)
+    }
+    /// Record that `fact` follows from the facts in `support`.
+    pub fn justify(&self, fact: FactKey, support: Vec<FactKey>) {
+        let mut dependents = self.dependents.borrow_mut();
+        for supporting in support.iter() {
+            dependents.entry(*supporting).or_default().insert(fact);
+        }
+        let mut supports = self.supports.borrow_mut();
+        let justifications = supports.entry(fact).or_default();
+        if !justifications.contains(&support) {
+            justifications.push(support);
+        }
+    }
+    /// Record the rule and bindings with which `fact` was derived from the facts in `support`.
+    pub fn explain(&self, fact: FactKey, (rule, name): (&'a str, Option<&'a str>), support: &[FactKey], bindings: Bindings) {
+        let mut derivations = self.derivations.borrow_mut();
+        let known = derivations.entry(fact).or_default();
+        if !known.iter().any(|old| old.rule == rule && old.support == support) {
+            known.push(Derivation { rule, name, support: support.to_vec(), bindings });
+        }
+    }
+    /// Keep the text of the fact whose absence is recorded with the key `negation`.
+    pub fn add_absent(&self, negation: FactKey, text: &'a str) {
+        self.absent.borrow_mut().insert(negation, text);
+    }
+    /// A proof of `fact` down to told facts, if it is in the knowledge base.
+    /// Among several derivations, the first recorded one that is not circular is chosen.
+    pub fn proof(&self, fact: FactKey) -> Option<Proof> {
+        self.prove(fact, &mut vec![], &mut HashMap::new())
+    }
+    fn prove(&self, fact: FactKey, visiting: &mut Vec<FactKey>, proven: &mut HashMap<FactKey, Proof>) -> Option<Proof> {
+        if let Some(proof) = proven.get(&fact) {
+            return Some(proof.clone());
+        }
+        if visiting.contains(&fact) {
+            return None;
+        }
+        let proof = if self.told.borrow().contains(&fact) {
+            match (self.texts.borrow().get(&fact), self.absent.borrow().get(&fact)) {
+                (Some(text), _) => Proof { fact: text.to_string(), reason: Reason::Told },
+                (None, Some(text)) => Proof { fact: text.to_string(), reason: Reason::Absent },
+                (None, None) => return None,
+            }
+        } else {
+            let text = self.texts.borrow().get(&fact)?.to_string();
+            let derivations: Vec<(&'a str, Option<&'a str>, Vec<FactKey>, Bindings)> = self.derivations.borrow()
+                .get(&fact)
+                .map(|derivations| derivations.iter()
+                    .map(|derivation| (derivation.rule, derivation.name, derivation.support.clone(), derivation.bindings.clone()))
+                    .collect())
+                .unwrap_or_default();
+            let supports = self.supports.borrow().get(&fact).cloned().unwrap_or_default();
+            visiting.push(fact);
+            let mut reason = None;
+            for (rule, name, support, bindings) in derivations {
+                if let Some(from) = self.prove_all(&support, visiting, proven) {
+                    let name = name.map(String::from);
+                    reason = Some(Reason::Derived { rule: rule.trim().to_string(), name, bindings, from });
+                    break;
+                }
+            }
+            if reason.is_none() {
+                for support in supports {
+                    if let Some(from) = self.prove_all(&support, visiting, proven) {
+                        reason = Some(Reason::Supported { from });
+                        break;
+                    }
+                }
+            }
+            visiting.pop();
+            Proof { fact: text, reason: reason? }
+        };
+        proven.insert(fact, proof.clone());
+        Some(proof)
+    }
+    fn prove_all(&self, facts: &[FactKey], visiting: &mut Vec<FactKey>, proven: &mut HashMap<FactKey, Proof>) -> Option<Vec<Proof>> {
+        facts.iter().map(|fact| self.prove(*fact, visiting, proven)).collect()
+    }
+    /// The texts of the facts in the knowledge base, with their keys.
+    pub fn facts(&self) -> Vec<(FactKey, &'a str)> {
+        self.texts.borrow().iter().map(|(key, text)| (*key, *text)).collect()
+    }
+    /// The keys of the facts that were told, and of the negations that hold.
+    pub fn told(&self) -> Vec<FactKey> {
+        self.told.borrow().iter().cloned().collect()
+    }
+    /// The recorded justifications of each derived fact.
+    pub fn supports(&self) -> Vec<(FactKey, Vec<Vec<FactKey>>)> {
+        self.supports.borrow().iter().map(|(key, supports)| (*key, supports.clone())).collect()
+    }
+    /// The clock, and the times at which the facts and negations started to hold.
+    pub fn stamps(&self) -> (u64, Vec<(FactKey, u64)>) {
+        (self.clock.get(), self.times.borrow().iter().map(|(key, time)| (*key, *time)).collect())
+    }
+    /// Set the clock, and the times at which the facts and negations started to hold,
+    /// saved with `stamps`.
+    pub fn restore_stamps(&self, clock: u64, times: Vec<(FactKey, u64)>) {
+        self.clock.set(clock);
+        self.times.borrow_mut().extend(times);
+    }
+    /// The texts of the facts whose absence is recorded, with the keys of the negations.
+    pub fn absent(&self) -> Vec<(FactKey, &'a str)> {
+        self.absent.borrow().iter().map(|(key, text)| (*key, *text)).collect()
+    }
+    /// Record that `node` holds a rule derived from the facts in `support`.
+    pub fn derive_rule(&self, support: &[FactKey], node: &'a RSNode<'a>) {
+        let mut rule_nodes = self.rule_nodes.borrow_mut();
+        for supporting in support {
+            let nodes = rule_nodes.entry(*supporting).or_default();
+            if !nodes.iter().any(|old| std::ptr::eq(*old, node)) {
+                nodes.push(node);
+            }
+        }
+    }
+    /// Withdraw the told status of `fact`, and find out which facts
+    /// are left without any justification as a consequence.
+    /// The rules derived from those facts are removed from their nodes,
+    /// and the texts of the facts are returned, to be removed by the caller.
+    ///
+    /// All facts depending on `fact` are first considered lost;
+    /// then, those among them that can still be justified
+    /// with facts that are not lost are recovered, until no more can be.
+    pub fn retract(&self, fact: FactKey) -> Vec<&'a str> {
+        let told = self.told.borrow_mut().remove(&fact);
+        if !told && !self.supports.borrow().contains_key(&fact) {
+            return vec![];
+        }
+        let mut lost: HashSet<FactKey> = HashSet::new();
+        let mut pending = vec![fact];
+        {
+            let told = self.told.borrow();
+            let dependents = self.dependents.borrow();
+            while let Some(next) = pending.pop() {
+                if !lost.insert(next) {
+                    continue;
+                }
+                if let Some(deps) = dependents.get(&next) {
+                    pending.extend(deps.iter().filter(|dep| !told.contains(*dep)));
+                }
+            }
+        }
+        let mut recovered: HashSet<FactKey> = HashSet::new();
+        {
+            let supports = self.supports.borrow();
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for candidate in lost.iter() {
+                    if recovered.contains(candidate) {
+                        continue;
+                    }
+                    let justified = supports.get(candidate).is_some_and(|justifications| {
+                        justifications.iter().any(|support| {
+                            support.iter().all(|s| !lost.contains(s) || recovered.contains(s))
+                        })
+                    });
+                    if justified {
+                        recovered.insert(*candidate);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        let lost: Vec<FactKey> = lost.into_iter()
+            .filter(|fact| !recovered.contains(fact))
+            .collect();
+        self.forget(&lost)
+    }
+    fn forget(&self, lost: &[FactKey]) -> Vec<&'a str> {
+        let mut texts = self.texts.borrow_mut();
+        let mut supports = self.supports.borrow_mut();
+        let mut dependents = self.dependents.borrow_mut();
+        let mut rule_nodes = self.rule_nodes.borrow_mut();
+        let mut derivations = self.derivations.borrow_mut();
+        let mut absent = self.absent.borrow_mut();
+        let mut times = self.times.borrow_mut();
+        let mut facts: Vec<&'a str> = vec![];
+        let mut nodes: Vec<&'a RSNode<'a>> = vec![];
+        for fact in lost {
+            if let Some(text) = texts.remove(fact) {
+                facts.push(text);
+            }
+            for support in supports.remove(fact).unwrap_or_default() {
+                for supporting in support {
+                    if let Some(deps) = dependents.get_mut(&supporting) {
+                        deps.remove(fact);
+                    }
+                }
+            }
+            derivations.remove(fact);
+            absent.remove(fact);
+            times.remove(fact);
+            for dependent in dependents.remove(fact).unwrap_or_default() {
+                if let Some(justifications) = supports.get_mut(&dependent) {
+                    justifications.retain(|support| !support.contains(fact));
+                }
+                if let Some(known) = derivations.get_mut(&dependent) {
+                    known.retain(|derivation| !derivation.support.contains(fact));
+                }
+            }
+            for node in rule_nodes.remove(fact).unwrap_or_default() {
+                if !nodes.iter().any(|old| std::ptr::eq(*old, node)) {
+                    nodes.push(node);
+                }
+            }
+        }
+        for node in nodes {
+            node.retain_rule_refs(|rule_ref| {
+                !rule_ref.rule.support.iter().any(|fact| lost.contains(fact))
+            });
+        }
+        facts
+    }
+}
+impl<'a> Default for Justifications<'a> {
+    fn default() -> Self {
+        Justifications::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retract_with_alternative_and_circular_support() {
+        let justs = Justifications::new();
+        for (key, text) in [(1, "a"), (2, "b"), (3, "c"), (4, "d"), (5, "e")].iter() {
+            justs.add_text(*key, text);
+        }
+        justs.tell(1);
+        justs.tell(2);
+        // c follows from a, and also from b; d and e from each other and from c
+        justs.justify(3, vec![1]);
+        justs.justify(3, vec![2]);
+        justs.justify(4, vec![3]);
+        justs.justify(5, vec![4]);
+        justs.justify(4, vec![5]);
+
+        assert_eq!(justs.retract(1), vec!["a"]);
+
+        let mut lost = justs.retract(2);
+        lost.sort();
+        assert_eq!(lost, vec!["b", "c", "d", "e"]);
+        assert!(justs.retract(3).is_empty());
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        // keys are kept in snapshots, so the hash must not change
+        let mut hasher = Fnv::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -1,11 +1,14 @@
+use std::collections::HashSet;
+
 use pest::Parser;
 //use log::debug;
 
+use crate::error::MPError;
 use crate::lexicon::Lexicon;
 use crate::matching::MPMatching;
 use crate::segment::MPSegment;
-use crate::transform_str::TSParser;
-use crate::transform_num::TNParser;
+use crate::transform_str::{ TSParser, StrExpr };
+use crate::transform_num::{ TNParser, NumExpr };
 
 #[derive(Parser)]
 #[grammar = "transform.pest"]
@@ -15,6 +18,19 @@ pub struct TParser<'a> {
     str_parser: TSParser<'a>,
 }
 
+#[derive(Debug)]
+pub enum Transform<'a> {
+    Num(&'a MPSegment, NumExpr<'a>),
+    Str(&'a MPSegment, StrExpr<'a>),
+}
+
+/// The compiled transforms section of an antecedent.
+#[derive(Debug)]
+pub struct Transforms<'a> {
+    pub source: &'a str,
+    pub transforms: Vec<Transform<'a>>,
+}
+
 impl<'a> TParser<'a> {
 
     pub fn new(lexicon: &'a Lexicon) -> TParser<'a> {
@@ -25,36 +41,40 @@ impl<'a> TParser<'a> {
         }
     }
 
-    pub fn process_transforms(&'a self, source: &'a str, mut matching: MPMatching<'a>) -> MPMatching<'a> {
-        let mut var: &MPSegment;
-        let mut val: &MPSegment;
-
-        let parse_result = TParser::parse(Rule::transforms, source);
-        if parse_result.is_err() {
-            panic!("These do not seem like transforms: \"{}\"\n\nerr: {}\n\nmatching: {:?}", source, parse_result.err().unwrap(), matching);
-        }
-        let mut pairs = parse_result.ok().unwrap();
-
+    /// Compile a transforms section, checking that the variables used in it
+    /// are in `bound`, to which the variables it assigns are added.
+    pub fn compile(source: &'a str, lexicon: &'a Lexicon, bound: &mut HashSet<String>) -> Result<Transforms<'a>, MPError> {
+        let mut pairs = match TParser::parse(Rule::transforms, source) {
+            Ok(pairs) => pairs,
+            Err(err) => return Err(MPError::transform(source, err)),
+        };
+        let mut transforms = vec![];
         for pair in pairs.next().unwrap().into_inner() {
-            val = match pair.as_rule() {
-                Rule::num_transform => {
-                    let mut asspair = pair.into_inner();
-                    let varpair = asspair.next().expect("a variable");
-                    var = self.lexicon.intern("var", varpair.as_str(), true);
-                    let exprpair = asspair.next().expect("an expression");
-                    self.num_parser.compile(exprpair.as_str(), &matching)
-                },
-                Rule::str_transform => {
-                    let mut asspair = pair.into_inner();
-                    let varpair = asspair.next().expect("a variable");
-                    var = self.lexicon.intern("var", varpair.as_str(), true);
-                    let exprpair = asspair.next().expect("an expression");
-                    self.str_parser.compile(exprpair.as_str(), &matching)
-                },
+            let kind = pair.as_rule();
+            let mut asspair = pair.into_inner();
+            let varpair = asspair.next().expect("a variable");
+            let var = lexicon.intern("var", varpair.as_str(), true);
+            let exprpair = asspair.next().expect("an expression");
+            let offset = exprpair.as_span().start();
+            let transform = match kind {
+                Rule::num_transform => Transform::Num(var, TNParser::compile(source, exprpair.as_str(), offset, lexicon, bound)?),
+                Rule::str_transform => Transform::Str(var, TSParser::compile(source, exprpair.as_str(), offset, lexicon, bound)?),
                 unknown_expr => panic!("Unexpected expression: {:?}", unknown_expr),
             };
+            transforms.push(transform);
+            bound.insert(varpair.as_str().to_string());
+        }
+        Ok(Transforms { source, transforms })
+    }
+
+    pub fn process_transforms(&'a self, transforms: &Transforms<'a>, matching: &mut MPMatching<'a>) -> Result<(), MPError> {
+        for transform in transforms.transforms.iter() {
+            let (var, val) = match transform {
+                Transform::Num(var, expr) => (*var, self.num_parser.eval(transforms.source, expr, matching)?),
+                Transform::Str(var, expr) => (*var, self.str_parser.eval(transforms.source, expr, matching)?),
+            };
             matching.insert(var, val);
         }
-        matching
+        Ok(())
     }
 }
@@ -1,6 +1,9 @@
+use std::collections::HashSet;
+
 use pest::Parser;
 //use log::debug;
 
+use crate::error::{ MPError, line_col };
 use crate::lexicon::Lexicon;
 use crate::matching::MPMatching;
 use crate::segment::MPSegment;
@@ -11,6 +14,16 @@ pub struct TNParser<'a> {
     lexicon: &'a Lexicon,
 }
 
+/// A compiled numeric expression. Variables keep their byte offset
+/// in the source of the transforms, to report errors.
+#[derive(Debug)]
+pub enum NumExpr<'a> {
+    Number(f64),
+    Var(&'a MPSegment, usize),
+    Monadic(&'a str, Box<NumExpr<'a>>),
+    Dyadic(&'a str, Box<NumExpr<'a>>, Box<NumExpr<'a>>),
+}
+
 impl<'a> TNParser<'a> {
 
     pub fn new(lexicon: &'a Lexicon) -> TNParser<'a> {
@@ -19,68 +32,95 @@ impl<'a> TNParser<'a> {
         }
     }
 
-    pub fn compile(&self, source: &str, matching: &MPMatching<'a>) -> &MPSegment {
-        let parse_result = TNParser::parse(Rule::expr, source);
+    /// Compile the numeric expression `text`, found at byte `offset` of `source`,
+    /// checking that the variables it uses are in `bound`.
+    pub fn compile(source: &'a str, text: &'a str, offset: usize, lexicon: &'a Lexicon, bound: &HashSet<String>) -> Result<NumExpr<'a>, MPError> {
+        let parse_result = TNParser::parse(Rule::expr, text);
         if parse_result.is_err() {
-            panic!("These do not seem like transforms: \"{}\"\n\nerr: {}\n\nmatching: {:?}", source, parse_result.err().unwrap(), matching);
+            return Err(MPError::transform(text, parse_result.err().unwrap()).within(source, offset));
         }
         let pair = parse_result.ok().unwrap().next().unwrap();
-        let num = format!("{}", self.compile_num(pair, matching));
-        self.lexicon.intern_with_text("v_decimal", num, true)
+        TNParser::compile_pair(source, offset, pair, lexicon, bound)
     }
 
-    fn compile_num(&self, pair: pest::iterators::Pair<Rule>, matching: &MPMatching<'a>) -> f64 {
+    fn compile_pair(source: &'a str, offset: usize, pair: pest::iterators::Pair<'a, Rule>, lexicon: &'a Lexicon, bound: &HashSet<String>) -> Result<NumExpr<'a>, MPError> {
         match pair.as_rule() {
             Rule::expr => {
-                self.compile_num(pair.into_inner().next().expect("cua"), matching)
+                TNParser::compile_pair(source, offset, pair.into_inner().next().expect("cua"), lexicon, bound)
             },
             Rule::monadicExpr => {
                 let mut pair = pair.into_inner();
                 let op = pair.next().expect("cin");
                 let termpair = pair.next().expect("sei");
-                let term = self.compile_num(termpair, matching);
-                parse_monadic_op(op, term)
+                let term = TNParser::compile_pair(source, offset, termpair, lexicon, bound)?;
+                Ok(NumExpr::Monadic(op.as_str(), Box::new(term)))
             },
             Rule::dyadicExpr => {
                 let mut pair = pair.into_inner();
                 let lhspair = pair.next().expect("sie");
-                let lhs = self.compile_num(lhspair, matching);
+                let lhs = TNParser::compile_pair(source, offset, lhspair, lexicon, bound)?;
                 let op = pair.next().expect("och");
                 let rhspair = pair.next().expect("nue");
-                let rhs = self.compile_num(rhspair, matching);
-                parse_dyadic_op(op, lhs, rhs)
+                let rhs = TNParser::compile_pair(source, offset, rhspair, lexicon, bound)?;
+                Ok(NumExpr::Dyadic(op.as_str(), Box::new(lhs), Box::new(rhs)))
             },
             Rule::v_decimal => {
-                pair.as_str().parse::<f64>().ok().expect("die")
+                Ok(NumExpr::Number(pair.as_str().parse::<f64>().expect("die")))
             },
             Rule::var => {
-                let var = self.lexicon.intern("var", pair.as_str(), true);
-                let number = matching.get(var).expect("number segment");
-                let result = number.text.parse::<f64>();
-                if result.is_err() {
-                    panic!("This do not seem like a number: \"{}\"\n\nerr: {}\n\nmatching: {:?}", number.text, result.err().unwrap(), matching);
+                let var_offset = offset + pair.as_span().start();
+                if !bound.contains(pair.as_str()) {
+                    let pos = line_col(source, var_offset);
+                    return Err(MPError::bad_transform(source, pos, format!("variable {} is not bound", pair.as_str())));
                 }
-                result.ok().unwrap()
+                Ok(NumExpr::Var(lexicon.intern("var", pair.as_str(), true), var_offset))
             },
             unknown_expr => panic!("Unexpected expression: {:?}", unknown_expr),
         }
     }
+
+    pub fn eval(&self, source: &str, expr: &NumExpr<'a>, matching: &MPMatching<'a>) -> Result<&'a MPSegment, MPError> {
+        let num = format!("{}", eval_num(source, expr, matching)?);
+        Ok(self.lexicon.intern_with_text("v_decimal", num, true))
+    }
+}
+
+fn eval_num<'a>(source: &str, expr: &NumExpr<'a>, matching: &MPMatching<'a>) -> Result<f64, MPError> {
+    match expr {
+        NumExpr::Number(num) => Ok(*num),
+        NumExpr::Var(var, offset) => {
+            let number = match matching.get(var) {
+                Some(number) => number,
+                None => return Err(MPError::bad_transform(source, line_col(source, *offset), format!("variable {} is not bound", var.text))),
+            };
+            match number.text.parse::<f64>() {
+                Ok(num) => Ok(num),
+                Err(_) => Err(MPError::bad_transform(source, line_col(source, *offset), format!("\"{}\", bound to {}, is not a number", number.text, var.text))),
+            }
+        },
+        NumExpr::Monadic(op, term) => {
+            Ok(parse_monadic_op(op, eval_num(source, term, matching)?))
+        },
+        NumExpr::Dyadic(op, lhs, rhs) => {
+            Ok(parse_dyadic_op(op, eval_num(source, lhs, matching)?, eval_num(source, rhs, matching)?))
+        },
+    }
 }
 
-fn parse_dyadic_op(op: pest::iterators::Pair<Rule>, lhs: f64, rhs: f64) -> f64 {
-    match op.as_str() {
+fn parse_dyadic_op(op: &str, lhs: f64, rhs: f64) -> f64 {
+    match op {
         "-" => lhs - rhs,
         "+" => lhs + rhs,
         "**" => lhs.powf(rhs),
         "*" => lhs * rhs,
         "/" => lhs / rhs,
         "%" => (lhs % rhs),
-        _ => panic!("Unexpected dyadic operator: {}", op.as_str()),
+        _ => panic!("Unexpected dyadic operator: {}", op),
     }
 }
 
-fn parse_monadic_op(op: pest::iterators::Pair<Rule>, term: f64) -> f64 {
-    match op.as_str() {
+fn parse_monadic_op(op: &str, term: f64) -> f64 {
+    match op {
         "-" => - term,
         "log" => term.log2(),
         "exp" => - term.exp(),
@@ -92,7 +132,7 @@ fn parse_monadic_op(op: pest::iterators::Pair<Rule>, term: f64) -> f64 {
         "asin" => - term.asin(),
         "acos" => - term.acos(),
         "atan" => - term.atan(),
-        _ => panic!("Unexpected dyadic verb: {}", op.as_str()),
+        _ => panic!("Unexpected dyadic verb: {}", op),
     }
 }
 
@@ -1,6 +1,9 @@
+use std::collections::HashSet;
+
 use pest::Parser;
 //use log::debug;
 
+use crate::error::{ MPError, line_col };
 use crate::lexicon::Lexicon;
 use crate::matching::MPMatching;
 use crate::segment::MPSegment;
@@ -13,6 +16,17 @@ pub struct TSParser<'a> {
     lexicon: &'a Lexicon,
 }
 
+/// A compiled string expression. Variables and triadic operations
+/// keep their byte offset in the source of the transforms, to report errors.
+#[derive(Debug)]
+pub enum StrExpr<'a> {
+    Segment(&'a MPSegment),
+    Var(&'a MPSegment, usize),
+    Monadic(&'a str, Box<StrExpr<'a>>),
+    Dyadic(&'a str, Box<StrExpr<'a>>, Box<StrExpr<'a>>),
+    Triadic(&'a str, usize, Box<StrExpr<'a>>, Box<StrExpr<'a>>, Box<StrExpr<'a>>),
+}
+
 impl<'a> TSParser<'a> {
 
     pub fn new(lexicon: &'a Lexicon) -> TSParser<'a> {
@@ -21,81 +35,114 @@ impl<'a> TSParser<'a> {
         }
     }
 
-    pub fn compile(&self, source: &str, matching: &MPMatching<'a>) -> &MPSegment {
-        let parse_result = SParser::parse(Rule::expr, source);
+    /// Compile the string expression `text`, found at byte `offset` of `source`,
+    /// checking that the variables it uses are in `bound`.
+    pub fn compile(source: &'a str, text: &'a str, offset: usize, lexicon: &'a Lexicon, bound: &HashSet<String>) -> Result<StrExpr<'a>, MPError> {
+        let parse_result = SParser::parse(Rule::expr, text);
         if parse_result.is_err() {
-            panic!("These do not seem like transforms: \"{}\"\n\nerr: {}\n\nmatching: {:?}", source, parse_result.err().unwrap(), matching);
+            return Err(MPError::transform(text, parse_result.err().unwrap()).within(source, offset));
         }
         let pair = parse_result.ok().unwrap().next().unwrap();
-
-        self.compile_expr(pair, matching)
+        TSParser::compile_pair(source, offset, pair.into_inner().next().expect("cua"), lexicon, bound)
     }
 
-    fn compile_expr(&self, pair: pest::iterators::Pair<Rule>, matching: &MPMatching<'a>) -> &MPSegment {
+    fn compile_pair(source: &'a str, offset: usize, pair: pest::iterators::Pair<'a, Rule>, lexicon: &'a Lexicon, bound: &HashSet<String>) -> Result<StrExpr<'a>, MPError> {
         match pair.as_rule() {
             Rule::monadicExpr => {
                 let mut pair = pair.into_inner();
                 let op = pair.next().expect("cin");
                 let termpair = pair.next().expect("sei");
-                let term = self.compile_expr(termpair, matching);
-                self.parse_monadic_op(op, term)
+                let term = TSParser::compile_pair(source, offset, termpair, lexicon, bound)?;
+                Ok(StrExpr::Monadic(op.as_str(), Box::new(term)))
             },
             Rule::dyadicExpr => {
                 let mut pair = pair.into_inner();
                 let op = pair.next().expect("och");
                 let fstpair = pair.next().expect("sie");
-                let fst = self.compile_expr(fstpair, matching);
+                let fst = TSParser::compile_pair(source, offset, fstpair, lexicon, bound)?;
                 let sndpair = pair.next().expect("nue");
-                let snd = self.compile_expr(sndpair, matching);
-                self.parse_dyadic_op(op, fst, snd)
+                let snd = TSParser::compile_pair(source, offset, sndpair, lexicon, bound)?;
+                Ok(StrExpr::Dyadic(op.as_str(), Box::new(fst), Box::new(snd)))
             },
             Rule::triadicExpr => {
                 let mut pair = pair.into_inner();
                 let op = pair.next().expect("och");
+                let op_offset = offset + op.as_span().start();
                 let fstpair = pair.next().expect("sie");
-                let fst = self.compile_expr(fstpair, matching);
+                let fst = TSParser::compile_pair(source, offset, fstpair, lexicon, bound)?;
                 let sndpair = pair.next().expect("nue");
-                let snd = self.compile_expr(sndpair, matching);
+                let snd = TSParser::compile_pair(source, offset, sndpair, lexicon, bound)?;
                 let trdpair = pair.next().expect("nue");
-                let trd = self.compile_expr(trdpair, matching);
-                self.parse_triadic_op(op, fst, snd, trd)
+                let trd = TSParser::compile_pair(source, offset, trdpair, lexicon, bound)?;
+                Ok(StrExpr::Triadic(op.as_str(), op_offset, Box::new(fst), Box::new(snd), Box::new(trd)))
             },
             Rule::v_decimal => {
-                self.lexicon.intern("v_decimal", pair.as_str(), true)
+                Ok(StrExpr::Segment(lexicon.intern("v_decimal", pair.as_str(), true)))
             },
             Rule::v_string => {
-                self.lexicon.intern("v_string", pair.as_str(), true)
+                Ok(StrExpr::Segment(lexicon.intern("v_string", pair.as_str(), true)))
             },
             Rule::var => {
-                let var = self.lexicon.intern("var", pair.as_str(), true);
-                matching.get(var).expect("segment")
+                let var_offset = offset + pair.as_span().start();
+                if !bound.contains(pair.as_str()) {
+                    let pos = line_col(source, var_offset);
+                    return Err(MPError::bad_transform(source, pos, format!("variable {} is not bound", pair.as_str())));
+                }
+                Ok(StrExpr::Var(lexicon.intern("var", pair.as_str(), true), var_offset))
             },
             unknown_expr => panic!("Unexpected expression: {:?}", unknown_expr),
         }
     }
 
-    fn parse_triadic_op(&self, op: pest::iterators::Pair<Rule>, fst: &MPSegment, snd: &MPSegment, trd: &MPSegment) -> &MPSegment {
-        match op.as_str() {
+    pub fn eval(&self, source: &str, expr: &StrExpr<'a>, matching: &MPMatching<'a>) -> Result<&'a MPSegment, MPError> {
+        match expr {
+            StrExpr::Segment(segment) => Ok(segment),
+            StrExpr::Var(var, offset) => {
+                match matching.get(var) {
+                    Some(segment) => Ok(segment),
+                    None => Err(MPError::bad_transform(source, line_col(source, *offset), format!("variable {} is not bound", var.text))),
+                }
+            },
+            StrExpr::Monadic(op, term) => {
+                let term = self.eval(source, term, matching)?;
+                Ok(self.parse_monadic_op(op, term))
+            },
+            StrExpr::Dyadic(op, fst, snd) => {
+                let fst = self.eval(source, fst, matching)?;
+                let snd = self.eval(source, snd, matching)?;
+                Ok(self.parse_dyadic_op(op, fst, snd))
+            },
+            StrExpr::Triadic(op, offset, fst, snd, trd) => {
+                let fst = self.eval(source, fst, matching)?;
+                let snd = self.eval(source, snd, matching)?;
+                let trd = self.eval(source, trd, matching)?;
+                self.parse_triadic_op(source, line_col(source, *offset), op, fst, snd, trd)
+            },
+        }
+    }
+
+    fn parse_triadic_op(&self, source: &str, pos: (usize, usize), op: &str, fst: &MPSegment, snd: &MPSegment, trd: &MPSegment) -> Result<&'a MPSegment, MPError> {
+        match op {
             "substring" => {
                 let fst_str = &fst.text;
-                let snd_num = snd.text.as_str().parse::<usize>().ok().expect("die");
-                let trd_num = trd.text.as_str().parse::<usize>().ok().expect("die");
+                let snd_num = parse_index(source, pos, snd)?;
+                let trd_num = parse_index(source, pos, trd)?;
                 let substr = fst_str.chars().skip(snd_num).take(trd_num).collect();
-                self.lexicon.intern_with_text(&fst.name, substr, true)
+                Ok(self.lexicon.intern_with_text(&fst.name, substr, true))
             },
             "replace" => {
                 let fst_str = &fst.text;
                 let snd_str = &snd.text;
                 let trd_str = &trd.text;
                 let result = fst_str.replace(snd_str, trd_str);
-                self.lexicon.intern_with_text(&fst.name, result, true)
+                Ok(self.lexicon.intern_with_text(&fst.name, result, true))
             },
-            _ => panic!("Unexpected triadic operator: {}", op.as_str()),
+            _ => panic!("Unexpected triadic operator: {}", op),
         }
     }
 
-    fn parse_dyadic_op(&self, op: pest::iterators::Pair<Rule>, fst: &MPSegment, snd: &MPSegment) -> &MPSegment {
-        match op.as_str() {
+    fn parse_dyadic_op(&self, op: &str, fst: &MPSegment, snd: &MPSegment) -> &'a MPSegment {
+        match op {
             "index_of" => {
                 let fst_str = &fst.text;
                 let snd_str = &snd.text;
@@ -110,17 +157,24 @@ impl<'a> TSParser<'a> {
                 fst_str.push_str(&snd.text);
                 self.lexicon.intern_with_text(&fst.name, fst_str, true)
             },
-            _ => panic!("Unexpected dyadic operator: {}", op.as_str()),
+            _ => panic!("Unexpected dyadic operator: {}", op),
         }
     }
 
-    fn parse_monadic_op(&self, op: pest::iterators::Pair<Rule>, term: &MPSegment) -> &MPSegment {
-        match op.as_str() {
+    fn parse_monadic_op(&self, op: &str, term: &MPSegment) -> &'a MPSegment {
+        match op {
             "len" => {
                 let result = format!("{}", term.text.len());
                 self.lexicon.intern_with_text("v_decimal", result, true)
             },
-            _ => panic!("Unexpected monadic operator: {}", op.as_str()),
+            _ => panic!("Unexpected monadic operator: {}", op),
         }
     }
 }
+
+fn parse_index(source: &str, pos: (usize, usize), segment: &MPSegment) -> Result<usize, MPError> {
+    match segment.text.parse::<usize>() {
+        Ok(index) => Ok(index),
+        Err(_) => Err(MPError::bad_transform(source, pos, format!("\"{}\" is not an index", segment.text))),
+    }
+}